@@ -0,0 +1,435 @@
+use anyhow::{Context, Result};
+use chrono::{Local, TimeZone};
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AlertConfig {
+    // A device silent this long is reported offline
+    pub offline_sec: i64,
+    // Sensor errors within error_burst_min minutes that raise an alert
+    pub error_burst_count: usize,
+    pub error_burst_min: u32,
+    // No new notification for the same alert within this time, against flapping
+    pub cooldown_min: u32,
+    // Also notify when an alert is over
+    pub notify_resolved: bool,
+    pub notifiers: Vec<NotifierConfig>,
+}
+
+impl Default for AlertConfig {
+    fn default() -> Self {
+        AlertConfig {
+            offline_sec: 300,
+            error_burst_count: 5,
+            error_burst_min: 10,
+            cooldown_min: 60,
+            notify_resolved: true,
+            notifiers: Vec::new(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpSecurity {
+    #[default]
+    Starttls,
+    Tls,
+    // Plain text, only for a relay on localhost
+    None,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum NotifierConfig {
+    // POSTs the alert as JSON
+    Webhook { url: String },
+    // ntfy topic URL, e.g. https://ntfy.sh/heating
+    Ntfy { url: String, token: Option<String> },
+    // Gotify server URL and application token
+    Gotify { url: String, token: String },
+    Smtp {
+        server: String,
+        port: Option<u16>,
+        #[serde(default)]
+        security: SmtpSecurity,
+        username: Option<String>,
+        password: Option<String>,
+        from: String,
+        to: Vec<String>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertKind {
+    SensorOffline,
+    RelayOffline,
+    RelayUnconfirmed,
+    SensorErrors,
+    TemperatureOutOfBounds,
+    HeaterFault,
+    DeviceRestarted,
+}
+
+impl AlertKind {
+    fn description(&self) -> &'static str {
+        match self {
+            AlertKind::SensorOffline => "sensor offline",
+            AlertKind::RelayOffline => "relay offline",
+            AlertKind::RelayUnconfirmed => "relay not confirming commands",
+            AlertKind::SensorErrors => "sensor errors",
+            AlertKind::TemperatureOutOfBounds => "temperature out of bounds",
+            AlertKind::HeaterFault => "heater fault",
+            AlertKind::DeviceRestarted => "device restarted",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertState {
+    Firing,
+    Resolved,
+}
+
+// Notification payload, also the webhook body
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Alert {
+    pub kind: AlertKind,
+    // Room or device the alert is about, e.g. "bedroom"
+    pub subject: String,
+    pub state: AlertState,
+    pub message: String,
+    // When the condition started
+    pub since: i64,
+    pub timestamp: i64,
+}
+
+impl Alert {
+    pub fn title(&self) -> String {
+        let state = match self.state {
+            AlertState::Firing => "ALERT",
+            AlertState::Resolved => "RESOLVED",
+        };
+        format!("{}: {} {}", state, self.subject, self.kind.description())
+    }
+}
+
+struct ActiveAlert {
+    since: i64,
+    notified: bool,
+}
+
+// Deduplicates conditions reported on every message into notifications
+pub struct AlertManager {
+    cooldown_sec: i64,
+    notify_resolved: bool,
+    active: HashMap<(AlertKind, String), ActiveAlert>,
+    // Last notification per alert, kept after it resolves for the cool-down
+    last_notified: HashMap<(AlertKind, String), i64>,
+    sender: mpsc::UnboundedSender<Alert>,
+}
+
+impl AlertManager {
+    pub fn new(config: &AlertConfig, sender: mpsc::UnboundedSender<Alert>) -> AlertManager {
+        AlertManager {
+            cooldown_sec: config.cooldown_min as i64 * 60,
+            notify_resolved: config.notify_resolved,
+            active: HashMap::new(),
+            last_notified: HashMap::new(),
+            sender,
+        }
+    }
+
+    // Condition present, notifies once when it starts
+    pub fn raise(&mut self, kind: AlertKind, subject: &str, message: String, now: i64) {
+        let key = (kind, subject.to_string());
+        if self.active.contains_key(&key) {
+            return;
+        }
+        let notified = self.notify_firing(&key, message, now);
+        self.active.insert(key, ActiveAlert { since: now, notified });
+    }
+
+    // Condition gone, notifies when the start was notified
+    pub fn resolve(&mut self, kind: AlertKind, subject: &str, message: String, now: i64) {
+        let key = (kind, subject.to_string());
+        let Some(active) = self.active.remove(&key) else {
+            return;
+        };
+        info!(?kind, subject, "alert resolved");
+        if active.notified && self.notify_resolved {
+            self.send(Alert { kind, subject: key.1, state: AlertState::Resolved, message, since: active.since, timestamp: now });
+        }
+    }
+
+    // One-off event without a resolved state, e.g. a device restart
+    pub fn event(&mut self, kind: AlertKind, subject: &str, message: String, now: i64) {
+        let key = (kind, subject.to_string());
+        self.notify_firing(&key, message, now);
+    }
+
+    pub fn is_active(&self, kind: AlertKind, subject: &str) -> bool {
+        self.active.contains_key(&(kind, subject.to_string()))
+    }
+
+    fn notify_firing(&mut self, key: &(AlertKind, String), message: String, now: i64) -> bool {
+        let (kind, subject) = key;
+        warn!(?kind, subject, message, "alert");
+        if self.last_notified.get(key).is_some_and(|&last| now - last < self.cooldown_sec) {
+            return false;
+        }
+        self.last_notified.insert(key.clone(), now);
+        self.send(Alert { kind: *kind, subject: subject.clone(), state: AlertState::Firing, message, since: now, timestamp: now });
+        true
+    }
+
+    fn send(&self, alert: Alert) {
+        // Fails only when the notifier task is gone
+        let _ = self.sender.send(alert);
+    }
+}
+
+// Delivers alerts to all notifiers in the background
+pub fn spawn_notifiers(notifiers: Vec<NotifierConfig>) -> mpsc::UnboundedSender<Alert> {
+    let (sender, mut receiver) = mpsc::unbounded_channel::<Alert>();
+    let client = http_client();
+    tokio::spawn(async move {
+        while let Some(alert) = receiver.recv().await {
+            for notifier in &notifiers {
+                if let Err(e) = notify(&client, notifier, &alert).await {
+                    warn!(error = format!("{:#}", e), notifier = notifier_name(notifier), "failed to send alert");
+                }
+            }
+        }
+    });
+    sender
+}
+
+fn http_client() -> reqwest::Client {
+    // reqwest and lettre use rustls, which needs a process-wide crypto provider
+    let _ = rustls::crypto::ring::default_provider().install_default();
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(30))
+        .build()
+        .expect("Failed to create HTTP client")
+}
+
+fn notifier_name(notifier: &NotifierConfig) -> &'static str {
+    match notifier {
+        NotifierConfig::Webhook { .. } => "webhook",
+        NotifierConfig::Ntfy { .. } => "ntfy",
+        NotifierConfig::Gotify { .. } => "gotify",
+        NotifierConfig::Smtp { .. } => "smtp",
+    }
+}
+
+pub async fn notify(client: &reqwest::Client, notifier: &NotifierConfig, alert: &Alert) -> Result<()> {
+    let firing = alert.state == AlertState::Firing;
+    match notifier {
+        NotifierConfig::Webhook { url } => {
+            client.post(url).json(alert).send().await?.error_for_status()?;
+        }
+        NotifierConfig::Ntfy { url, token } => {
+            let mut request = client.post(url)
+                .header("Title", alert.title())
+                .header("Priority", if firing { "high" } else { "default" })
+                .header("Tags", if firing { "warning" } else { "white_check_mark" })
+                .body(alert.message.clone());
+            if let Some(token) = token {
+                request = request.bearer_auth(token);
+            }
+            request.send().await?.error_for_status()?;
+        }
+        NotifierConfig::Gotify { url, token } => {
+            client.post(format!("{}/message", url.trim_end_matches('/')))
+                .header("X-Gotify-Key", token)
+                .json(&serde_json::json!({
+                    "title": alert.title(),
+                    "message": alert.message,
+                    "priority": if firing { 8 } else { 4 },
+                }))
+                .send().await?.error_for_status()?;
+        }
+        NotifierConfig::Smtp { server, port, security, username, password, from, to } => {
+            let since = Local.timestamp_opt(alert.since, 0).single().map(|t| t.format("%Y-%m-%d %H:%M").to_string());
+            let body = format!("{}\n\nSince: {}\n", alert.message, since.unwrap_or_default());
+            let mut message = Message::builder()
+                .from(from.parse::<Mailbox>().context("Invalid from address")?)
+                .subject(alert.title());
+            for address in to {
+                message = message.to(address.parse::<Mailbox>().with_context(|| format!("Invalid address {}", address))?);
+            }
+            let message = message.body(body)?;
+
+            let mut transport = match security {
+                SmtpSecurity::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(server)?,
+                SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(server)?,
+                SmtpSecurity::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(server),
+            };
+            if let Some(port) = port {
+                transport = transport.port(*port);
+            }
+            if let (Some(username), Some(password)) = (username, password) {
+                transport = transport.credentials(Credentials::new(username.clone(), password.clone()));
+            }
+            transport.build().send(message).await?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::State;
+    use axum::http::HeaderMap;
+    use axum::routing::post;
+    use axum::Router;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    fn alert(state: AlertState) -> Alert {
+        Alert {
+            kind: AlertKind::SensorOffline,
+            subject: "bedroom".to_string(),
+            state,
+            message: "No readings for 5 minutes".to_string(),
+            since: 1000,
+            timestamp: 1300,
+        }
+    }
+
+    #[test]
+    fn dedup_cooldown_and_resolve() {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let mut alerts = AlertManager::new(&AlertConfig::default(), sender);
+
+        alerts.raise(AlertKind::SensorOffline, "bedroom", "offline".to_string(), 0);
+        alerts.raise(AlertKind::SensorOffline, "bedroom", "offline".to_string(), 60);
+        assert_eq!(receiver.try_recv().unwrap().state, AlertState::Firing);
+        assert!(receiver.try_recv().is_err());
+
+        alerts.resolve(AlertKind::SensorOffline, "bedroom", "back".to_string(), 120);
+        let resolved = receiver.try_recv().unwrap();
+        assert_eq!((resolved.state, resolved.since), (AlertState::Resolved, 0));
+
+        // Flapping within the cool-down is not notified, neither is its resolve
+        alerts.raise(AlertKind::SensorOffline, "bedroom", "offline".to_string(), 180);
+        assert!(alerts.is_active(AlertKind::SensorOffline, "bedroom"));
+        alerts.resolve(AlertKind::SensorOffline, "bedroom", "back".to_string(), 240);
+        assert!(receiver.try_recv().is_err());
+
+        alerts.raise(AlertKind::SensorOffline, "bedroom", "offline".to_string(), 3600);
+        assert_eq!(receiver.try_recv().unwrap().since, 3600);
+    }
+
+    type Received = Arc<Mutex<Vec<(HeaderMap, String)>>>;
+
+    async fn receive(State(received): State<Received>, headers: HeaderMap, body: String) {
+        received.lock().unwrap().push((headers, body));
+    }
+
+    // Stand-in HTTP server recording every POST
+    async fn http_server() -> (String, Received) {
+        let received = Received::default();
+        let app = Router::new().route("/{*path}", post(receive)).with_state(received.clone());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (url, received)
+    }
+
+    #[tokio::test]
+    async fn http_notifiers() {
+        let (url, received) = http_server().await;
+        let client = http_client();
+
+        notify(&client, &NotifierConfig::Webhook { url: format!("{}/hook", url) }, &alert(AlertState::Firing)).await.unwrap();
+        notify(&client, &NotifierConfig::Ntfy { url: format!("{}/heating", url), token: Some("tk".to_string()) },
+            &alert(AlertState::Resolved)).await.unwrap();
+        notify(&client, &NotifierConfig::Gotify { url: format!("{}/", url), token: "app".to_string() },
+            &alert(AlertState::Firing)).await.unwrap();
+
+        let received = received.lock().unwrap();
+        assert_eq!(serde_json::from_str::<Alert>(&received[0].1).unwrap(), alert(AlertState::Firing));
+
+        let (headers, body) = &received[1];
+        assert_eq!(headers["title"], "RESOLVED: bedroom sensor offline");
+        assert_eq!(headers["authorization"], "Bearer tk");
+        assert_eq!(body, "No readings for 5 minutes");
+
+        let (headers, body) = &received[2];
+        assert_eq!(headers["x-gotify-key"], "app");
+        assert_eq!(serde_json::from_str::<serde_json::Value>(body).unwrap()["priority"], 8);
+    }
+
+    // Minimal SMTP server accepting one message
+    async fn smtp_server() -> (u16, tokio::task::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            writer.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+            let mut data = String::new();
+            let mut in_data = false;
+            while let Some(line) = lines.next_line().await.unwrap() {
+                if in_data {
+                    if line == "." {
+                        in_data = false;
+                        writer.write_all(b"250 OK\r\n").await.unwrap();
+                    } else {
+                        data.push_str(&line);
+                        data.push('\n');
+                    }
+                    continue;
+                }
+                let command = line.to_ascii_uppercase();
+                let reply: &[u8] = if command.starts_with("EHLO") {
+                    b"250 localhost\r\n"
+                } else if command.starts_with("DATA") {
+                    in_data = true;
+                    b"354 End data with <CR><LF>.<CR><LF>\r\n"
+                } else if command.starts_with("QUIT") {
+                    writer.write_all(b"221 Bye\r\n").await.unwrap();
+                    break;
+                } else {
+                    b"250 OK\r\n"
+                };
+                writer.write_all(reply).await.unwrap();
+            }
+            data
+        });
+        (port, handle)
+    }
+
+    #[tokio::test]
+    async fn smtp_notifier() {
+        let (port, server) = smtp_server().await;
+        let notifier = NotifierConfig::Smtp {
+            server: "127.0.0.1".to_string(),
+            port: Some(port),
+            security: SmtpSecurity::None,
+            username: None,
+            password: None,
+            from: "heating@example.com".to_string(),
+            to: vec!["admin@example.com".to_string()],
+        };
+        notify(&http_client(), &notifier, &alert(AlertState::Firing)).await.unwrap();
+        let data = server.await.unwrap();
+        assert!(data.contains("Subject: ALERT: bedroom sensor offline"));
+        assert!(data.contains("No readings for 5 minutes"));
+    }
+}
@@ -0,0 +1,346 @@
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender, SyncSender};
+use std::sync::Mutex;
+
+use crate::button::RoomMode;
+use crate::fault::HeaterFault;
+use crate::pwm::ControlDiagnostics;
+use crate::safety::SafetyIntervention;
+use tracing::error;
+
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuditConfig {
+    // JSON lines file, rotated to path.1 .. path.N
+    pub path: PathBuf,
+    pub max_bytes: u64,
+    pub max_files: u32,
+    // Recent events kept in memory for /api/events/log
+    pub memory_events: usize,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        AuditConfig {
+            path: PathBuf::from("/var/lib/temperature/audit.log"),
+            max_bytes: 5_000_000,
+            max_files: 5,
+            memory_events: 5000,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AuditKind {
+    // Relay or disable request from the web UI / API
+    WebAction { user: String, source: String, action: String, room: String },
+    // Result of Control::get_mode for a sensor report
+    ControlDecision {
+        device_id: u32,
+        temperature: f64,
+        target: f64,
+        heater_on: bool,
+        delay_ms: u32,
+        disabled: bool,
+        diagnostics: ControlDiagnostics,
+    },
+    // Command sent to a relay, error is set when sending failed
+    RelayCommand {
+        relay: String,
+        on: bool,
+        delay_ms: u32,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    // New command sent while the previous one was never confirmed
+    RelayUnconfirmed { relay: String },
+    // Relay report after a command, with the actual relay state
+    RelayConfirmed { device_id: u32, on: bool },
+    DeviceStarted { device_id: u32, source: String },
+    DeviceOffline { device_id: u32, source: String, offline_sec: u32 },
+    // Sensor went silent, the relay follows the room's failsafe mode
+    FailsafeStarted { device_id: u32, room: String, silent_sec: i64, mode: String },
+    // Sensor reports again, normal control resumed
+    FailsafeEnded { device_id: u32, room: String },
+    // Safety limit overrode the requested relay state
+    SafetyOverride {
+        device_id: u32,
+        room: String,
+        temperature: f64,
+        intervention: SafetyIntervention,
+        requested_on: bool,
+    },
+    // Sharp temperature drop, heating paused until `paused_until`
+    WindowOpen { device_id: u32, room: String, temp_drop: f64, humidity_drop: f64, paused_until: i64 },
+    // Heater or relay fault detected, and cleared again
+    FaultRaised { device_id: u32, room: String, fault: HeaterFault },
+    FaultCleared { device_id: u32, room: String, fault: String },
+    // Sensor button pressed, `mode` is the room's new mode, None back to the schedule
+    ButtonPressed { device_id: u32, room: String, mode: Option<RoomMode> },
+    // Forced heat or boost ran out
+    ButtonModeEnded { device_id: u32, room: String, mode: RoomMode },
+    // Boiler output switched, `rooms` are the rooms and controllers calling for heat
+    HeatDemand { on: bool, rooms: Vec<String> },
+    // Server stopping on a signal, relays are set to their shutdown state
+    Shutdown { signal: String },
+}
+
+impl AuditKind {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            AuditKind::WebAction { .. } => "web_action",
+            AuditKind::ControlDecision { .. } => "control_decision",
+            AuditKind::RelayCommand { .. } => "relay_command",
+            AuditKind::RelayUnconfirmed { .. } => "relay_unconfirmed",
+            AuditKind::RelayConfirmed { .. } => "relay_confirmed",
+            AuditKind::DeviceStarted { .. } => "device_started",
+            AuditKind::DeviceOffline { .. } => "device_offline",
+            AuditKind::FailsafeStarted { .. } => "failsafe_started",
+            AuditKind::FailsafeEnded { .. } => "failsafe_ended",
+            AuditKind::SafetyOverride { .. } => "safety_override",
+            AuditKind::WindowOpen { .. } => "window_open",
+            AuditKind::FaultRaised { .. } => "fault_raised",
+            AuditKind::FaultCleared { .. } => "fault_cleared",
+            AuditKind::ButtonPressed { .. } => "button_pressed",
+            AuditKind::ButtonModeEnded { .. } => "button_mode_ended",
+            AuditKind::HeatDemand { .. } => "heat_demand",
+            AuditKind::Shutdown { .. } => "shutdown",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AuditEvent {
+    pub timestamp: i64,
+    #[serde(flatten)]
+    pub kind: AuditKind,
+}
+
+#[derive(Deserialize, Default)]
+pub struct AuditQuery {
+    // Only events newer than this timestamp
+    pub since: Option<i64>,
+    // Only events of this type, e.g. "web_action"
+    #[serde(rename = "type")]
+    pub kind: Option<String>,
+    // Newest events first, at most this many
+    pub limit: Option<usize>,
+}
+
+enum WriterMessage {
+    Event(AuditEvent),
+    // Answered once the events before it are written
+    Flush(SyncSender<()>),
+}
+
+// Owns the file, so rotation and disk errors never block the callers of record()
+struct AuditWriter {
+    config: AuditConfig,
+    file: Option<File>,
+    file_size: u64,
+    write_failed: bool,
+}
+
+pub struct AuditLog {
+    config: AuditConfig,
+    recent: Mutex<VecDeque<AuditEvent>>,
+    writer: Sender<WriterMessage>,
+}
+
+impl AuditLog {
+    pub fn new(config: AuditConfig) -> AuditLog {
+        let recent = load_recent(&config.path, config.memory_events);
+        // Opened on the first write, which also reports errors
+        let mut writer = AuditWriter { config: config.clone(), file: None, file_size: 0, write_failed: false };
+        let (sender, receiver) = channel();
+        std::thread::Builder::new()
+            .name("audit-writer".to_string())
+            .spawn(move || writer.run(receiver))
+            .expect("Failed to start the audit writer");
+        AuditLog { config, recent: Mutex::new(recent), writer: sender }
+    }
+
+    pub fn record(&self, kind: AuditKind) {
+        let event = AuditEvent { timestamp: Local::now().timestamp(), kind };
+        let _ = self.writer.send(WriterMessage::Event(event.clone()));
+
+        let mut recent = self.recent.lock().unwrap();
+        recent.push_back(event);
+        while recent.len() > self.config.memory_events {
+            recent.pop_front();
+        }
+    }
+
+    // Waits until the recorded events are written, e.g. before exiting
+    pub fn flush(&self) {
+        let (done, written) = sync_channel(1);
+        if self.writer.send(WriterMessage::Flush(done)).is_ok() {
+            let _ = written.recv();
+        }
+    }
+
+    pub fn query(&self, query: &AuditQuery) -> Vec<AuditEvent> {
+        let recent = self.recent.lock().unwrap();
+        recent.iter()
+            .rev()
+            .filter(|event| query.since.is_none_or(|since| event.timestamp > since))
+            .filter(|event| query.kind.as_deref().is_none_or(|kind| event.kind.type_name() == kind))
+            .take(query.limit.unwrap_or(500))
+            .cloned()
+            .collect()
+    }
+}
+
+impl AuditWriter {
+    fn run(&mut self, receiver: Receiver<WriterMessage>) {
+        for message in receiver {
+            match message {
+                WriterMessage::Event(event) => match self.write(&event) {
+                    Ok(()) => self.write_failed = false,
+                    Err(e) => {
+                        // Report once, not for every event while the disk is unavailable
+                        if !self.write_failed {
+                            error!(path = %self.config.path.display(), "Error writing audit log: {}", e);
+                        }
+                        self.write_failed = true;
+                        self.file = None;
+                    }
+                },
+                WriterMessage::Flush(done) => {
+                    let _ = done.send(());
+                }
+            }
+        }
+    }
+
+    fn write(&mut self, event: &AuditEvent) -> std::io::Result<()> {
+        let mut line = serde_json::to_string(event)?;
+        line.push('\n');
+
+        if self.file.is_none() {
+            // First write, or retry after an earlier failure
+            let (file, size) = open_append(&self.config.path)?;
+            self.file = Some(file);
+            self.file_size = size;
+        }
+
+        if self.file_size > 0 && self.file_size + line.len() as u64 > self.config.max_bytes {
+            self.file = None;
+            rotate(&self.config.path, self.config.max_files)?;
+            let (file, size) = open_append(&self.config.path)?;
+            self.file = Some(file);
+            self.file_size = size;
+        }
+
+        if let Some(file) = self.file.as_mut() {
+            file.write_all(line.as_bytes())?;
+            self.file_size += line.len() as u64;
+        }
+        Ok(())
+    }
+}
+
+fn open_append(path: &Path) -> std::io::Result<(File, u64)> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let size = file.metadata()?.len();
+    Ok((file, size))
+}
+
+fn rotated_path(path: &Path, index: u32) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", index));
+    PathBuf::from(name)
+}
+
+// audit.log -> audit.log.1 -> ... -> audit.log.N (dropped)
+fn rotate(path: &Path, max_files: u32) -> std::io::Result<()> {
+    if max_files == 0 {
+        return fs::remove_file(path);
+    }
+    for index in (1..max_files).rev() {
+        let from = rotated_path(path, index);
+        if from.exists() {
+            fs::rename(&from, rotated_path(path, index + 1))?;
+        }
+    }
+    fs::rename(path, rotated_path(path, 1))
+}
+
+// Fill the in-memory buffer from the current file, so queries survive restarts
+fn load_recent(path: &Path, limit: usize) -> VecDeque<AuditEvent> {
+    let mut recent = VecDeque::new();
+    let Ok(file) = File::open(path) else {
+        return recent;
+    };
+    for line in BufReader::new(file).lines().map_while(Result::ok) {
+        if let Ok(event) = serde_json::from_str::<AuditEvent>(&line) {
+            recent.push_back(event);
+            if recent.len() > limit {
+                recent.pop_front();
+            }
+        }
+    }
+    recent
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_config(name: &str, max_bytes: u64) -> AuditConfig {
+        let dir = std::env::temp_dir().join(format!("temperature-audit-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        AuditConfig { path: dir.join("audit.log"), max_bytes, max_files: 2, memory_events: 100 }
+    }
+
+    fn web_action(room: &str) -> AuditKind {
+        AuditKind::WebAction {
+            user: "admin".to_string(),
+            source: "192.168.0.5".to_string(),
+            action: "disable".to_string(),
+            room: room.to_string(),
+        }
+    }
+
+    #[test]
+    fn query_and_reload() {
+        let config = temp_config("reload", 1_000_000);
+        let log = AuditLog::new(config.clone());
+        log.record(web_action("bedroom"));
+        log.record(AuditKind::RelayUnconfirmed { relay: "esp8266-relay0.local".to_string() });
+
+        let events = log.query(&AuditQuery { kind: Some("web_action".to_string()), ..Default::default() });
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].kind, web_action("bedroom"));
+
+        // Newest first
+        let all = log.query(&AuditQuery::default());
+        assert_eq!(all[0].kind.type_name(), "relay_unconfirmed");
+
+        log.flush();
+        let reloaded = AuditLog::new(config);
+        assert_eq!(reloaded.query(&AuditQuery::default()), all);
+    }
+
+    #[test]
+    fn rotation() {
+        let config = temp_config("rotate", 200);
+        let log = AuditLog::new(config.clone());
+        for _ in 0..20 {
+            log.record(web_action("kids_bedroom"));
+        }
+        log.flush();
+        assert!(fs::metadata(&config.path).unwrap().len() <= 200);
+        assert!(rotated_path(&config.path, 1).exists());
+        assert!(rotated_path(&config.path, 2).exists());
+        assert!(!rotated_path(&config.path, 3).exists());
+        assert_eq!(log.query(&AuditQuery::default()).len(), 20);
+    }
+}
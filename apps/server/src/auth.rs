@@ -0,0 +1,278 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use axum::http::{header, HeaderMap};
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+const SESSION_COOKIE: &str = "session";
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    // Can only read the status
+    Viewer,
+    // Can control the rooms listed in `rooms`
+    Operator,
+    // Can control everything
+    Admin,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct UserConfig {
+    pub name: String,
+    // Argon2 PHC string, generate with `temperature-server --hash-password`
+    #[serde(default)]
+    pub password_hash: Option<String>,
+    // Hex SHA-256 of an API token, generate with `temperature-server --new-token`
+    #[serde(default)]
+    pub token_sha256: Option<String>,
+    pub role: Role,
+    // Rooms an operator is allowed to control
+    #[serde(default)]
+    pub rooms: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    // Without users the API stays open, as before authentication existed
+    pub users: Vec<UserConfig>,
+    // Set to false only while serving plain HTTP
+    pub secure_cookies: bool,
+    pub session_hours: i64,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig {
+            users: Vec::new(),
+            secure_cookies: true,
+            session_hours: 24 * 30,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Debug)]
+pub struct User {
+    pub name: String,
+    pub role: Role,
+    pub rooms: Vec<String>,
+}
+
+impl User {
+    fn anonymous_admin() -> User {
+        User { name: "anonymous".to_string(), role: Role::Admin, rooms: Vec::new() }
+    }
+
+    pub fn can_control(&self, room: &str) -> bool {
+        match self.role {
+            Role::Admin => true,
+            Role::Operator => self.rooms.iter().any(|r| r == room),
+            Role::Viewer => false,
+        }
+    }
+}
+
+impl From<&UserConfig> for User {
+    fn from(config: &UserConfig) -> Self {
+        User { name: config.name.clone(), role: config.role, rooms: config.rooms.clone() }
+    }
+}
+
+struct Session {
+    user: String,
+    expires: i64,
+}
+
+pub struct Auth {
+    config: AuthConfig,
+    // Key: session id from the cookie
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn token_sha256(token: &str) -> String {
+    to_hex(&Sha256::digest(token.as_bytes()))
+}
+
+fn random_hex(len: usize) -> String {
+    let mut bytes = vec![0u8; len];
+    OsRng.fill_bytes(&mut bytes);
+    to_hex(&bytes)
+}
+
+pub fn hash_password(password: &str) -> anyhow::Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| anyhow::anyhow!("Failed to hash password: {}", e))?;
+    Ok(hash.to_string())
+}
+
+// Returns (token, hash to put into the config)
+pub fn new_token() -> (String, String) {
+    let token = random_hex(32);
+    let hash = token_sha256(&token);
+    (token, hash)
+}
+
+impl Auth {
+    pub fn new(config: AuthConfig) -> Auth {
+        Auth { config, sessions: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn enabled(&self) -> bool {
+        !self.config.users.is_empty()
+    }
+
+    // Checks the password and returns a new session id
+    pub fn login(&self, name: &str, password: &str) -> Option<String> {
+        let user = self.config.users.iter().find(|u| u.name == name)?;
+        let hash = PasswordHash::new(user.password_hash.as_deref()?).ok()?;
+        Argon2::default().verify_password(password.as_bytes(), &hash).ok()?;
+
+        let now = Local::now().timestamp();
+        let session_id = random_hex(32);
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|_, session| session.expires > now);
+        sessions.insert(session_id.clone(), Session {
+            user: user.name.clone(),
+            expires: now + self.config.session_hours * 3600,
+        });
+        Some(session_id)
+    }
+
+    pub fn logout(&self, headers: &HeaderMap) {
+        if let Some(session_id) = session_id(headers) {
+            self.sessions.lock().unwrap().remove(session_id);
+        }
+    }
+
+    pub fn session_cookie(&self, session_id: &str) -> String {
+        format!(
+            "{}={}; Path=/; HttpOnly; SameSite=Strict; Max-Age={}{}",
+            SESSION_COOKIE,
+            session_id,
+            self.config.session_hours * 3600,
+            if self.config.secure_cookies { "; Secure" } else { "" }
+        )
+    }
+
+    pub fn clear_cookie(&self) -> String {
+        format!(
+            "{}=; Path=/; HttpOnly; SameSite=Strict; Max-Age=0{}",
+            SESSION_COOKIE,
+            if self.config.secure_cookies { "; Secure" } else { "" }
+        )
+    }
+
+    // Resolves the user from a "Bearer" API token or the session cookie
+    pub fn authenticate(&self, headers: &HeaderMap) -> Option<User> {
+        if !self.enabled() {
+            return Some(User::anonymous_admin());
+        }
+
+        let bearer = headers.get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        if let Some(token) = bearer {
+            let hash = token_sha256(token.trim());
+            return self.config.users.iter()
+                .find(|u| u.token_sha256.as_deref().is_some_and(|h| h.eq_ignore_ascii_case(&hash)))
+                .map(User::from);
+        }
+
+        let session_id = session_id(headers)?;
+        let now = Local::now().timestamp();
+        let name = {
+            let sessions = self.sessions.lock().unwrap();
+            let session = sessions.get(session_id).filter(|s| s.expires > now)?;
+            session.user.clone()
+        };
+        self.config.users.iter().find(|u| u.name == name).map(User::from)
+    }
+}
+
+fn session_id(headers: &HeaderMap) -> Option<&str> {
+    headers.get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == SESSION_COOKIE)
+        .map(|(_, value)| value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn auth() -> Auth {
+        let token_hash = token_sha256("secret-token");
+        Auth::new(AuthConfig {
+            users: vec![
+                UserConfig {
+                    name: "admin".to_string(),
+                    password_hash: Some(hash_password("pass").unwrap()),
+                    token_sha256: None,
+                    role: Role::Admin,
+                    rooms: vec![],
+                },
+                UserConfig {
+                    name: "kids".to_string(),
+                    password_hash: None,
+                    token_sha256: Some(token_hash),
+                    role: Role::Operator,
+                    rooms: vec!["kids_bedroom".to_string()],
+                },
+            ],
+            ..AuthConfig::default()
+        })
+    }
+
+    #[test]
+    fn open_without_users() {
+        let auth = Auth::new(AuthConfig::default());
+        let user = auth.authenticate(&HeaderMap::new()).unwrap();
+        assert!(user.can_control("bedroom"));
+    }
+
+    #[test]
+    fn session_login() {
+        let auth = auth();
+        assert!(auth.authenticate(&HeaderMap::new()).is_none());
+        assert!(auth.login("admin", "wrong").is_none());
+
+        let session_id = auth.login("admin", "pass").unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(header::COOKIE, HeaderValue::from_str(&format!("theme=dark; session={}", session_id)).unwrap());
+        let user = auth.authenticate(&headers).unwrap();
+        assert_eq!(user.name, "admin");
+
+        auth.logout(&headers);
+        assert!(auth.authenticate(&headers).is_none());
+    }
+
+    #[test]
+    fn operator_token() {
+        let auth = auth();
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, HeaderValue::from_static("Bearer secret-token"));
+        let user = auth.authenticate(&headers).unwrap();
+        assert_eq!(user.role, Role::Operator);
+        assert!(user.can_control("kids_bedroom"));
+        assert!(!user.can_control("bedroom"));
+
+        headers.insert(header::AUTHORIZATION, HeaderValue::from_static("Bearer other"));
+        assert!(auth.authenticate(&headers).is_none());
+    }
+}
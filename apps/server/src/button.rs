@@ -0,0 +1,139 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ButtonConfig {
+    // What a press on a room's sensor does, unless overridden in `rooms`
+    pub action: ButtonAction,
+    // Heater forced ON for this long, a second press cancels
+    pub force_heat_min: i64,
+    // Degrees added to the target, each press goes one level up, after the last one boost ends
+    pub boost_levels: Vec<f64>,
+    pub boost_min: i64,
+    // Target while away, toggled by a press
+    pub away_temp: f64,
+    pub rooms: HashMap<String, RoomButton>,
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct RoomButton {
+    pub action: Option<ButtonAction>,
+    pub away_temp: Option<f64>,
+}
+
+impl Default for ButtonConfig {
+    fn default() -> Self {
+        ButtonConfig {
+            action: ButtonAction::ForceHeat,
+            force_heat_min: 60,
+            boost_levels: vec![1.0, 2.0, 3.0],
+            boost_min: 120,
+            away_temp: 16.0,
+            rooms: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ButtonAction {
+    ForceHeat,
+    Boost,
+    Away,
+}
+
+// Mode of a room set with the button
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum RoomMode {
+    // Heater ON regardless of the schedule
+    ForceHeat { until: i64 },
+    // Target raised by `degrees`, level counts from 1
+    Boost { level: usize, degrees: f64, until: i64 },
+    // Target lowered to `target` until the next press
+    Away { target: f64 },
+}
+
+impl RoomMode {
+    pub fn action(&self) -> ButtonAction {
+        match self {
+            RoomMode::ForceHeat { .. } => ButtonAction::ForceHeat,
+            RoomMode::Boost { .. } => ButtonAction::Boost,
+            RoomMode::Away { .. } => ButtonAction::Away,
+        }
+    }
+
+    pub fn expired(&self, now: i64) -> bool {
+        match self {
+            RoomMode::ForceHeat { until } | RoomMode::Boost { until, .. } => now >= *until,
+            RoomMode::Away { .. } => false,
+        }
+    }
+
+    // Schedule target adjusted for the mode
+    pub fn target(&self, scheduled: f64) -> f64 {
+        match self {
+            RoomMode::ForceHeat { .. } => scheduled,
+            RoomMode::Boost { degrees, .. } => scheduled + degrees,
+            RoomMode::Away { target } => scheduled.min(*target),
+        }
+    }
+}
+
+impl ButtonConfig {
+    // Mode after a press on the room's button, None returns to the schedule
+    pub fn press(&self, room: &str, current: Option<RoomMode>, now: i64) -> Option<RoomMode> {
+        let overrides = self.rooms.get(room).copied().unwrap_or_default();
+        let action = overrides.action.unwrap_or(self.action);
+        // A press for a different action than the active mode starts over
+        let current = current.filter(|mode| mode.action() == action && !mode.expired(now));
+        match (action, current) {
+            (ButtonAction::ForceHeat, Some(_)) | (ButtonAction::Away, Some(_)) => None,
+            (ButtonAction::ForceHeat, None) => Some(RoomMode::ForceHeat { until: now + self.force_heat_min * 60 }),
+            (ButtonAction::Away, None) => Some(RoomMode::Away { target: overrides.away_temp.unwrap_or(self.away_temp) }),
+            (ButtonAction::Boost, current) => {
+                let level = match current {
+                    Some(RoomMode::Boost { level, .. }) => level + 1,
+                    _ => 1,
+                };
+                let degrees = *self.boost_levels.get(level - 1)?;
+                Some(RoomMode::Boost { level, degrees, until: now + self.boost_min * 60 })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn press_cycles_modes() {
+        let config: ButtonConfig = toml::from_str(r#"
+            boost_levels = [1.0, 2.5]
+            [rooms]
+            bedroom = { action = "boost" }
+            kids_bedroom = { action = "away", away_temp = 17.0 }
+        "#).unwrap();
+
+        let force = config.press("irina", None, 0);
+        assert_eq!(force, Some(RoomMode::ForceHeat { until: 3600 }));
+        assert_eq!(config.press("irina", force, 60), None);
+        // Expired modes don't count, the press starts a new one
+        assert_eq!(config.press("irina", force, 4000), Some(RoomMode::ForceHeat { until: 7600 }));
+
+        let boost = config.press("bedroom", None, 0);
+        assert_eq!(boost.unwrap().target(20.0), 21.0);
+        let boost = config.press("bedroom", boost, 10);
+        assert_eq!(boost, Some(RoomMode::Boost { level: 2, degrees: 2.5, until: 7210 }));
+        assert_eq!(config.press("bedroom", boost, 20), None);
+
+        let away = config.press("kids_bedroom", None, 0).unwrap();
+        assert!(!away.expired(1_000_000));
+        assert_eq!(away.target(21.0), 17.0);
+        assert_eq!(away.target(15.0), 15.0);
+        assert_eq!(config.press("kids_bedroom", Some(away), 10), None);
+    }
+}
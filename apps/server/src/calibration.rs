@@ -0,0 +1,251 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CalibrationConfig {
+    // Device id of a trusted sensor placed next to the one being calibrated
+    pub reference_device: Option<u32>,
+    // Reference and sensor readings further apart than this are not paired
+    pub max_pair_age_sec: i64,
+    // Gain is only fitted when the readings span at least this many degrees
+    pub min_span: f64,
+    // Coefficients by sensor name: the room name for a room's own sensor, sensorN for others
+    pub sensors: HashMap<String, SensorCalibration>,
+}
+
+impl Default for CalibrationConfig {
+    fn default() -> Self {
+        CalibrationConfig { reference_device: None, max_pair_age_sec: 120, min_span: 2.0, sensors: HashMap::new() }
+    }
+}
+
+// corrected = raw * gain + offset
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct SensorCalibration {
+    pub temp_offset: f64,
+    pub temp_gain: f64,
+    pub humidity_offset: f64,
+    pub humidity_gain: f64,
+}
+
+impl Default for SensorCalibration {
+    fn default() -> Self {
+        SensorCalibration { temp_offset: 0.0, temp_gain: 1.0, humidity_offset: 0.0, humidity_gain: 1.0 }
+    }
+}
+
+impl SensorCalibration {
+    pub fn temperature(&self, raw: f64) -> f64 {
+        raw * self.temp_gain + self.temp_offset
+    }
+
+    pub fn humidity(&self, raw: f64) -> f64 {
+        (raw * self.humidity_gain + self.humidity_offset).clamp(0.0, 100.0)
+    }
+}
+
+// Raw sensor reading next to the reference, humidity only when the reference has one
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct CalibrationPair {
+    pub timestamp: i64,
+    pub raw_temp: f64,
+    pub reference_temp: f64,
+    pub raw_humidity: f64,
+    pub reference_humidity: Option<f64>,
+}
+
+#[derive(Default)]
+struct Session {
+    started_at: i64,
+    // Last raw (timestamp, temperature, humidity) of the sensor
+    last_raw: Option<(i64, f64, f64)>,
+    pairs: Vec<CalibrationPair>,
+}
+
+#[derive(Serialize)]
+pub struct CalibrationStatus {
+    pub sensor: String,
+    pub current: SensorCalibration,
+    pub active: bool,
+    pub started_at: Option<i64>,
+    pub pairs: Vec<CalibrationPair>,
+    // Computed from the pairs, to be copied into [calibration.sensors]
+    pub suggested: Option<SensorCalibration>,
+}
+
+// Applies the configured coefficients and collects readings in calibration mode.
+// Shared between the UDP server and the web API.
+pub struct Calibrator {
+    reference_device: Option<u32>,
+    max_pair_age_sec: i64,
+    min_span: f64,
+    sensors: HashMap<String, SensorCalibration>,
+    // Key: sensor name
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+impl Calibrator {
+    pub fn new(config: CalibrationConfig) -> Calibrator {
+        Calibrator {
+            reference_device: config.reference_device,
+            max_pair_age_sec: config.max_pair_age_sec,
+            min_span: config.min_span,
+            sensors: config.sensors,
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn calibration(&self, sensor: &str) -> SensorCalibration {
+        self.sensors.get(sensor).copied().unwrap_or_default()
+    }
+
+    pub fn is_reference(&self, device_id: u32) -> bool {
+        self.reference_device == Some(device_id)
+    }
+
+    // Starts over when already active
+    pub fn start(&self, sensor: &str, timestamp: i64) -> Result<(), &'static str> {
+        if !self.sensors.contains_key(sensor) {
+            return Err("Unknown sensor");
+        }
+        let session = Session { started_at: timestamp, ..Default::default() };
+        self.sessions.lock().unwrap().insert(sensor.to_string(), session);
+        Ok(())
+    }
+
+    pub fn stop(&self, sensor: &str) -> Option<CalibrationStatus> {
+        let session = self.sessions.lock().unwrap().remove(sensor)?;
+        Some(self.status_of(sensor, Some(&session), false))
+    }
+
+    // Raw reading of a room sensor, before calibration
+    pub fn sensor_reading(&self, sensor: &str, timestamp: i64, raw_temp: f64, raw_humidity: f64) {
+        if let Some(session) = self.sessions.lock().unwrap().get_mut(sensor) {
+            session.last_raw = Some((timestamp, raw_temp, raw_humidity));
+        }
+    }
+
+    // Reading of the reference device, paired with every sensor in calibration mode
+    pub fn reference_reading(&self, timestamp: i64, temp: f64, humidity: Option<f64>) {
+        for session in self.sessions.lock().unwrap().values_mut() {
+            self.add_pair(session, timestamp, temp, humidity);
+        }
+    }
+
+    // Reference reading entered by hand, e.g. from a calibrated thermometer
+    pub fn manual_reference(&self, sensor: &str, timestamp: i64, temp: f64, humidity: Option<f64>) -> Result<(), &'static str> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions.get_mut(sensor).ok_or("Calibration is not active for this sensor")?;
+        if self.add_pair(session, timestamp, temp, humidity) {
+            Ok(())
+        } else {
+            Err("No recent sensor reading to compare with")
+        }
+    }
+
+    // All calibrated sensors by sensor name
+    pub fn status(&self) -> Vec<CalibrationStatus> {
+        let sessions = self.sessions.lock().unwrap();
+        let mut sensors: Vec<&String> = self.sensors.keys().collect();
+        sensors.sort();
+        sensors.into_iter()
+            .map(|sensor| self.status_of(sensor, sessions.get(sensor), sessions.contains_key(sensor)))
+            .collect()
+    }
+
+    fn add_pair(&self, session: &mut Session, timestamp: i64, temp: f64, humidity: Option<f64>) -> bool {
+        let Some((raw_timestamp, raw_temp, raw_humidity)) = session.last_raw else {
+            return false;
+        };
+        if (timestamp - raw_timestamp).abs() > self.max_pair_age_sec {
+            return false;
+        }
+        session.pairs.push(CalibrationPair { timestamp, raw_temp, reference_temp: temp, raw_humidity, reference_humidity: humidity });
+        true
+    }
+
+    fn status_of(&self, sensor: &str, session: Option<&Session>, active: bool) -> CalibrationStatus {
+        let pairs = session.map(|session| session.pairs.clone()).unwrap_or_default();
+        CalibrationStatus {
+            sensor: sensor.to_string(),
+            current: self.calibration(sensor),
+            active,
+            started_at: session.map(|session| session.started_at),
+            suggested: suggest(&pairs, self.min_span),
+            pairs,
+        }
+    }
+}
+
+// Least squares fit of reference = raw * gain + offset.
+// Falls back to an offset only when the readings don't span `min_span`.
+fn fit(points: &[(f64, f64)], min_span: f64) -> Option<(f64, f64)> {
+    if points.is_empty() {
+        return None;
+    }
+    let n = points.len() as f64;
+    let mean_raw = points.iter().map(|(raw, _)| raw).sum::<f64>() / n;
+    let mean_reference = points.iter().map(|(_, reference)| reference).sum::<f64>() / n;
+    let min_raw = points.iter().map(|(raw, _)| *raw).fold(f64::MAX, f64::min);
+    let max_raw = points.iter().map(|(raw, _)| *raw).fold(f64::MIN, f64::max);
+    if max_raw - min_raw < min_span {
+        return Some((1.0, mean_reference - mean_raw));
+    }
+    let covariance: f64 = points.iter().map(|(raw, reference)| (raw - mean_raw) * (reference - mean_reference)).sum();
+    let variance: f64 = points.iter().map(|(raw, _)| (raw - mean_raw).powi(2)).sum();
+    let gain = covariance / variance;
+    Some((gain, mean_reference - gain * mean_raw))
+}
+
+fn suggest(pairs: &[CalibrationPair], min_span: f64) -> Option<SensorCalibration> {
+    let temps: Vec<_> = pairs.iter().map(|pair| (pair.raw_temp, pair.reference_temp)).collect();
+    let (temp_gain, temp_offset) = fit(&temps, min_span)?;
+    let humidities: Vec<_> = pairs.iter()
+        .filter_map(|pair| pair.reference_humidity.map(|reference| (pair.raw_humidity, reference)))
+        .collect();
+    // Humidity spans are larger, 5x the temperature span is comparable
+    let (humidity_gain, humidity_offset) = fit(&humidities, min_span * 5.0).unwrap_or((1.0, 0.0));
+    Some(SensorCalibration { temp_offset, temp_gain, humidity_offset, humidity_gain })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn approx(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-6
+    }
+
+    #[test]
+    fn two_point_fit() {
+        let mut config = CalibrationConfig { reference_device: Some(9), ..Default::default() };
+        config.sensors.insert("bedroom".to_string(), SensorCalibration::default());
+        let calibrator = Calibrator::new(config);
+        assert!(calibrator.start("hall", 0).is_err());
+        calibrator.start("bedroom", 0).unwrap();
+        // No sensor reading yet
+        assert!(calibrator.manual_reference("bedroom", 10, 15.0, None).is_err());
+
+        // Sensor reads 0.5 low at 15 and 0.9 low at 23
+        calibrator.sensor_reading("bedroom", 100, 14.5, 50.0);
+        calibrator.reference_reading(130, 15.0, Some(55.0));
+        let suggested = calibrator.status()[0].suggested.unwrap();
+        assert!(approx(suggested.temp_gain, 1.0) && approx(suggested.temp_offset, 0.5));
+
+        calibrator.sensor_reading("bedroom", 5000, 22.1, 40.0);
+        // Too far from the sensor reading
+        calibrator.reference_reading(5300, 23.0, None);
+        calibrator.manual_reference("bedroom", 5010, 23.0, Some(44.0)).unwrap();
+
+        let status = calibrator.stop("bedroom").unwrap();
+        assert_eq!(status.pairs.len(), 2);
+        let suggested = status.suggested.unwrap();
+        assert!(approx(suggested.temp_gain, 8.0 / 7.6));
+        assert!(approx(suggested.temperature(14.5), 15.0) && approx(suggested.temperature(22.1), 23.0));
+        assert!(approx(suggested.humidity(50.0), 55.0) && approx(suggested.humidity(40.0), 44.0));
+        assert!(!calibrator.status()[0].active);
+    }
+}
@@ -0,0 +1,63 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::path::Path;
+
+use crate::alert::AlertConfig;
+use crate::audit::AuditConfig;
+use crate::calibration::CalibrationConfig;
+use crate::control::ControlConfig;
+use crate::demand::HeatDemandConfig;
+use crate::diag::DiagConfig;
+use crate::auth::AuthConfig;
+use crate::button::ButtonConfig;
+use crate::devices::DevicesConfig;
+use crate::failsafe::FailsafeConfig;
+use crate::fault::FaultConfig;
+use crate::filter::SensorFilterConfig;
+use crate::fusion::SensorFusionConfig;
+use crate::logging::LogConfig;
+use crate::relay_guard::RelayProtectionConfig;
+use crate::safety::SafetyConfig;
+use crate::shutdown::ShutdownConfig;
+use crate::systemd::SystemdConfig;
+use crate::web::WebConfig;
+use crate::window::OpenWindowConfig;
+
+// Server configuration, loaded from a TOML file passed with --config.
+// Every section is optional, missing values fall back to defaults.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub web: WebConfig,
+    pub auth: AuthConfig,
+    pub audit: AuditConfig,
+    pub log: LogConfig,
+    pub failsafe: FailsafeConfig,
+    pub safety: SafetyConfig,
+    pub relay_protection: RelayProtectionConfig,
+    pub open_window: OpenWindowConfig,
+    pub fault: FaultConfig,
+    pub devices: DevicesConfig,
+    pub sensor_filter: SensorFilterConfig,
+    pub sensor_fusion: SensorFusionConfig,
+    pub calibration: CalibrationConfig,
+    pub button: ButtonConfig,
+    pub heat_demand: HeatDemandConfig,
+    pub diag: DiagConfig,
+    pub shutdown: ShutdownConfig,
+    pub systemd: SystemdConfig,
+    pub control: ControlConfig,
+    pub alert: AlertConfig,
+}
+
+impl Config {
+    pub fn load(path: Option<&Path>) -> Result<Config> {
+        let Some(path) = path else {
+            return Ok(Config::default());
+        };
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config {}", path.display()))?;
+        toml::from_str(&text)
+            .with_context(|| format!("Failed to parse config {}", path.display()))
+    }
+}
@@ -0,0 +1,16 @@
+use serde::Deserialize;
+
+// Strategies run with every reading and, between readings, on a periodic tick
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ControlConfig {
+    // Re-evaluates every room this often, so schedule changes, ended modes and disables,
+    // and delayed switches done by the relay apply without a new reading. 0 disables.
+    pub tick_sec: u64,
+}
+
+impl Default for ControlConfig {
+    fn default() -> Self {
+        ControlConfig { tick_sec: 60 }
+    }
+}
@@ -0,0 +1,149 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HeatDemandConfig {
+    pub enabled: bool,
+    pub mode: DemandMode,
+    // Weight of a room calling for heat by room name, 1.0 for rooms not listed
+    pub weights: HashMap<String, f64>,
+    // Weighted demand needed to run the boiler
+    pub threshold: f64,
+    // Boiler or pump relay, e.g. esp8266-boiler.local
+    pub relay: Option<String>,
+    // Devices controlling a central heat source, get a DeviceMessage with heat_on ("host:port")
+    pub publish: Vec<String>,
+    pub min_on_sec: i64,
+    pub min_off_sec: i64,
+    // Boiler or pump keeps running this long after the last room stopped calling
+    pub run_on_sec: i64,
+    // Output is repeated this often without a change
+    pub repeat_sec: i64,
+    // heat_on messages received from other controllers count as demand for this long
+    pub external_timeout_sec: i64,
+}
+
+impl Default for HeatDemandConfig {
+    fn default() -> Self {
+        HeatDemandConfig {
+            enabled: false,
+            mode: DemandMode::Any,
+            weights: HashMap::new(),
+            threshold: 1.0,
+            relay: None,
+            publish: Vec::new(),
+            min_on_sec: 300,
+            min_off_sec: 300,
+            run_on_sec: 120,
+            repeat_sec: 60,
+            external_timeout_sec: 300,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DemandMode {
+    // Any room calling for heat
+    Any,
+    // Sum of the weights of calling rooms reaches `threshold`
+    Weighted,
+}
+
+impl HeatDemandConfig {
+    // Weighted demand of the rooms calling for heat
+    pub fn weight(&self, calling: &[&str]) -> f64 {
+        calling.iter().map(|room| self.weights.get(*room).copied().unwrap_or(1.0)).sum()
+    }
+
+    pub fn demand(&self, calling: &[&str]) -> bool {
+        match self.mode {
+            DemandMode::Any => !calling.is_empty(),
+            DemandMode::Weighted => self.weight(calling) >= self.threshold,
+        }
+    }
+}
+
+// Boiler output with run-on and minimum times, timestamps are unix seconds
+#[derive(Default)]
+pub struct HeatDemand {
+    on: bool,
+    // None until the first switch, minimum times don't apply before
+    changed_at: Option<i64>,
+    last_demand: Option<i64>,
+    last_sent: Option<i64>,
+}
+
+impl HeatDemand {
+    pub fn on(&self) -> bool {
+        self.on
+    }
+
+    pub fn changed_at(&self) -> Option<i64> {
+        self.changed_at
+    }
+
+    // Returns the output when it has to be sent: after a switch or every repeat_sec
+    pub fn update(&mut self, config: &HeatDemandConfig, demand: bool, now: i64) -> Option<bool> {
+        if demand {
+            self.last_demand = Some(now);
+        }
+        let running_on = self.last_demand.is_some_and(|last| now - last < config.run_on_sec);
+        let wanted = demand || (self.on && running_on);
+        let min_sec = if self.on { config.min_on_sec } else { config.min_off_sec };
+        let switched = wanted != self.on && self.changed_at.is_none_or(|changed| now - changed >= min_sec);
+        if switched {
+            self.on = wanted;
+            self.changed_at = Some(now);
+        }
+        if switched || self.last_sent.is_none_or(|sent| now - sent >= config.repeat_sec) {
+            self.last_sent = Some(now);
+            return Some(self.on);
+        }
+        None
+    }
+}
+
+// Aggregated demand shown in /api/status
+#[derive(Default, Clone, Serialize, PartialEq)]
+pub struct HeatDemandState {
+    // Rooms calling for heat
+    pub rooms: Vec<String>,
+    pub weight: f64,
+    pub demand: bool,
+    // Boiler output, differs from demand during run-on and minimum times
+    pub on: bool,
+    pub since: Option<i64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run_on_and_minimum_times() {
+        let config = HeatDemandConfig { enabled: true, min_on_sec: 300, min_off_sec: 600, run_on_sec: 120, ..Default::default() };
+        let mut boiler = HeatDemand::default();
+        assert_eq!(boiler.update(&config, true, 0), Some(true));
+        assert_eq!(boiler.update(&config, true, 30), None);
+        // Demand ended, but the minimum on-time is not over yet
+        assert_eq!(boiler.update(&config, false, 100), Some(true));
+        assert!(boiler.on());
+        // Run-on and minimum on-time are over
+        assert_eq!(boiler.update(&config, false, 310), Some(false));
+        // Minimum off-time
+        assert_eq!(boiler.update(&config, true, 400), Some(false));
+        assert_eq!(boiler.update(&config, true, 910), Some(true));
+        assert_eq!(boiler.changed_at(), Some(910));
+
+        let config: HeatDemandConfig = toml::from_str(r#"
+            mode = "weighted"
+            threshold = 1.0
+            weights = { irina = 0.5, kids_bedroom = 0.5 }
+        "#).unwrap();
+        assert!(!config.demand(&["irina"]));
+        assert!(config.demand(&["irina", "kids_bedroom"]));
+        assert!(config.demand(&["bedroom"]));
+    }
+}
@@ -0,0 +1,317 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::Mutex;
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct DevicesConfig {
+    pub sensors: Vec<SensorDevice>,
+    pub relays: Vec<RelayDevice>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct SensorDevice {
+    // Firmware id from DeviceInfo
+    pub id: u32,
+    // Sensors of a room in the order they are listed, see also [sensor_fusion]
+    pub room: Option<String>,
+    // Identifies messages without an id
+    pub address: Option<IpAddr>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RelayDevice {
+    pub id: u32,
+    pub room: String,
+    // Where relay commands are sent, e.g. esp8266-relay0.local
+    pub host: String,
+    pub address: Option<IpAddr>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceKind {
+    Sensor,
+    Relay,
+}
+
+impl DeviceKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DeviceKind::Sensor => "sensor",
+            DeviceKind::Relay => "relay",
+        }
+    }
+}
+
+// Maps firmware ids and addresses to devices, and devices to rooms
+pub struct DeviceRegistry {
+    sensors: Vec<SensorDevice>,
+    relays: Vec<RelayDevice>,
+}
+
+impl DeviceRegistry {
+    pub fn new(config: DevicesConfig) -> DeviceRegistry {
+        DeviceRegistry { sensors: config.sensors, relays: config.relays }
+    }
+
+    // Firmware id of a message, the configured device of the source address when it has none
+    pub fn identify(&self, kind: DeviceKind, id: Option<u32>, address: IpAddr) -> Option<u32> {
+        id.or_else(|| match kind {
+            DeviceKind::Sensor => self.sensors.iter().find(|sensor| sensor.address == Some(address)).map(|sensor| sensor.id),
+            DeviceKind::Relay => self.relays.iter().find(|relay| relay.address == Some(address)).map(|relay| relay.id),
+        })
+    }
+
+    pub fn room_of(&self, kind: DeviceKind, id: u32) -> Option<&str> {
+        match kind {
+            DeviceKind::Sensor => self.sensors.iter().find(|sensor| sensor.id == id).and_then(|sensor| sensor.room.as_deref()),
+            DeviceKind::Relay => self.relays.iter().find(|relay| relay.id == id).map(|relay| relay.room.as_str()),
+        }
+    }
+
+    pub fn room_sensors(&self, room: &str) -> Vec<u32> {
+        self.sensors.iter()
+            .filter(|sensor| sensor.room.as_deref() == Some(room))
+            .map(|sensor| sensor.id)
+            .collect()
+    }
+
+    pub fn room_relay(&self, room: &str) -> Option<&RelayDevice> {
+        self.relays.iter().find(|relay| relay.room == room)
+    }
+
+    pub fn relays(&self) -> &[RelayDevice] {
+        &self.relays
+    }
+
+    // Rooms with at least one configured device
+    pub fn has_room(&self, room: &str) -> bool {
+        self.sensors.iter().any(|sensor| sensor.room.as_deref() == Some(room)) || self.room_relay(room).is_some()
+    }
+
+    // All configured devices
+    pub fn devices(&self) -> impl Iterator<Item = (DeviceKind, u32)> + '_ {
+        self.sensors.iter().map(|sensor| (DeviceKind::Sensor, sensor.id))
+            .chain(self.relays.iter().map(|relay| (DeviceKind::Relay, relay.id)))
+    }
+
+    // Name for calibration and filter settings: the room name for a room's first
+    // sensor, e.g. "sensor5" for others
+    pub fn sensor_name(&self, id: u32) -> String {
+        match self.room_of(DeviceKind::Sensor, id) {
+            Some(room) if self.room_sensors(room).first() == Some(&id) => room.to_string(),
+            _ => format!("sensor{}", id),
+        }
+    }
+
+    // Firmware id of a sensor name, the reverse of sensor_name
+    pub fn sensor_id(&self, name: &str) -> Option<u32> {
+        self.sensors.iter()
+            .map(|sensor| sensor.id)
+            .find(|&id| self.sensor_name(id) == name)
+            .or_else(|| name.strip_prefix("sensor")?.parse().ok())
+    }
+}
+
+// Devices are considered unavailable after this long without a message
+pub const AVAILABLE_SEC: i64 = 180;
+
+// Report intervals kept for the interval and jitter statistics
+const INTERVALS: usize = 50;
+// Addresses kept per device, the oldest are dropped
+const ADDRESSES: usize = 10;
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct AddressUse {
+    pub address: IpAddr,
+    pub first_seen: i64,
+    pub last_seen: i64,
+}
+
+#[derive(Default)]
+struct DeviceRecord {
+    first_seen: i64,
+    last_seen: i64,
+    addresses: VecDeque<AddressUse>,
+    restarts: u32,
+    offline_sec: u64,
+    messages: u64,
+    intervals: VecDeque<i64>,
+    errors: BTreeMap<String, u64>,
+}
+
+// Health of one device, for /api/devices
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct DeviceHealth {
+    pub kind: DeviceKind,
+    pub id: u32,
+    pub room: Option<String>,
+    // Listed in [devices], or one of the built-in devices
+    pub configured: bool,
+    pub available: bool,
+    // None for configured devices never heard from
+    pub first_seen: Option<i64>,
+    pub last_seen: Option<i64>,
+    // Newest last
+    pub addresses: Vec<AddressUse>,
+    pub restarts: u32,
+    // Sum of offline times reported by the device
+    pub offline_sec: u64,
+    pub messages: u64,
+    // Mean and standard deviation of recent report intervals, outages left out
+    pub interval_sec: Option<f64>,
+    pub jitter_sec: Option<f64>,
+    // Sensor errors by SensorError name
+    pub errors: BTreeMap<String, u64>,
+}
+
+// Message statistics of every device since startup.
+// Shared between the UDP server and the web API.
+#[derive(Default)]
+pub struct DeviceInventory {
+    records: Mutex<HashMap<(DeviceKind, u32), DeviceRecord>>,
+}
+
+impl DeviceInventory {
+    // Records a message, returns the address of the previous message
+    pub fn seen(&self, kind: DeviceKind, id: u32, address: IpAddr, started: bool, offline_sec: Option<u32>, now: i64) -> Option<IpAddr> {
+        let mut records = self.records.lock().unwrap();
+        let record = records.entry((kind, id)).or_insert_with(|| DeviceRecord { first_seen: now, ..Default::default() });
+        let previous = record.addresses.back().map(|used| used.address);
+        // A restart or an outage is no report interval
+        if record.messages > 0 && !started && offline_sec.is_none() {
+            record.intervals.push_back(now - record.last_seen);
+            if record.intervals.len() > INTERVALS {
+                record.intervals.pop_front();
+            }
+        }
+        record.last_seen = now;
+        record.messages += 1;
+        record.restarts += started as u32;
+        record.offline_sec += offline_sec.unwrap_or(0) as u64;
+        match record.addresses.back_mut() {
+            Some(used) if used.address == address => used.last_seen = now,
+            _ => {
+                record.addresses.push_back(AddressUse { address, first_seen: now, last_seen: now });
+                if record.addresses.len() > ADDRESSES {
+                    record.addresses.pop_front();
+                }
+            }
+        }
+        previous
+    }
+
+    pub fn sensor_error(&self, id: u32, error: &str) {
+        let mut records = self.records.lock().unwrap();
+        if let Some(record) = records.get_mut(&(DeviceKind::Sensor, id)) {
+            *record.errors.entry(error.to_string()).or_default() += 1;
+        }
+    }
+
+    pub fn last_seen(&self, kind: DeviceKind, id: u32) -> Option<i64> {
+        self.records.lock().unwrap().get(&(kind, id)).map(|record| record.last_seen)
+    }
+
+    // Configured and seen devices, sensors first, by id
+    pub fn health(&self, registry: &DeviceRegistry, now: i64) -> Vec<DeviceHealth> {
+        let records = self.records.lock().unwrap();
+        let mut keys: Vec<(DeviceKind, u32)> = registry.devices().chain(records.keys().copied()).collect();
+        keys.sort_by_key(|(kind, id)| (*kind == DeviceKind::Relay, *id));
+        keys.dedup();
+        keys.into_iter()
+            .map(|(kind, id)| {
+                let record = records.get(&(kind, id));
+                let intervals: Vec<f64> = record.map(|record| record.intervals.iter().map(|&sec| sec as f64).collect()).unwrap_or_default();
+                let interval_sec = (!intervals.is_empty()).then(|| intervals.iter().sum::<f64>() / intervals.len() as f64);
+                let jitter_sec = interval_sec.map(|mean| {
+                    (intervals.iter().map(|sec| (sec - mean).powi(2)).sum::<f64>() / intervals.len() as f64).sqrt()
+                });
+                DeviceHealth {
+                    kind,
+                    id,
+                    room: registry.room_of(kind, id).map(str::to_string),
+                    configured: registry.devices().any(|device| device == (kind, id)),
+                    available: record.is_some_and(|record| now - record.last_seen < AVAILABLE_SEC),
+                    first_seen: record.map(|record| record.first_seen),
+                    last_seen: record.map(|record| record.last_seen),
+                    addresses: record.map(|record| record.addresses.iter().cloned().collect()).unwrap_or_default(),
+                    restarts: record.map_or(0, |record| record.restarts),
+                    offline_sec: record.map_or(0, |record| record.offline_sec),
+                    messages: record.map_or(0, |record| record.messages),
+                    interval_sec,
+                    jitter_sec,
+                    errors: record.map(|record| record.errors.clone()).unwrap_or_default(),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookups() {
+        let config: DevicesConfig = toml::from_str(r#"
+            [[sensors]]
+            id = 7
+            room = "bedroom"
+            address = "192.168.0.200"
+            [[sensors]]
+            id = 8
+            room = "bedroom"
+            [[relays]]
+            id = 3
+            room = "bedroom"
+            host = "esp8266-relay0.local"
+        "#).unwrap();
+        let registry = DeviceRegistry::new(config);
+        let address: IpAddr = "192.168.0.200".parse().unwrap();
+        assert_eq!(registry.identify(DeviceKind::Sensor, None, address), Some(7));
+        assert_eq!(registry.identify(DeviceKind::Relay, None, address), None);
+        assert_eq!(registry.identify(DeviceKind::Sensor, Some(8), address), Some(8));
+        assert_eq!(registry.room_of(DeviceKind::Relay, 3), Some("bedroom"));
+        assert_eq!(registry.room_sensors("bedroom"), vec![7, 8]);
+        assert_eq!(registry.room_relay("bedroom").unwrap().host, "esp8266-relay0.local");
+        assert_eq!(registry.sensor_name(7), "bedroom");
+        assert_eq!(registry.sensor_name(8), "sensor8");
+        assert_eq!(registry.sensor_id("bedroom"), Some(7));
+        assert_eq!(registry.sensor_id("sensor8"), Some(8));
+        assert_eq!(registry.sensor_id("kids_bedroom"), None);
+        assert!(registry.has_room("bedroom"));
+        assert!(!registry.has_room("kids_bedroom"));
+    }
+
+    #[test]
+    fn inventory() {
+        let registry = DeviceRegistry::new(toml::from_str("[[relays]]\nid = 3\nroom = \"bedroom\"\nhost = \"relay\"").unwrap());
+        let inventory = DeviceInventory::default();
+        let first: IpAddr = "192.168.0.200".parse().unwrap();
+        let second: IpAddr = "192.168.0.201".parse().unwrap();
+        assert_eq!(inventory.seen(DeviceKind::Sensor, 7, first, true, None, 0), None);
+        assert_eq!(inventory.seen(DeviceKind::Sensor, 7, first, false, None, 60), Some(first));
+        assert_eq!(inventory.seen(DeviceKind::Sensor, 7, first, false, None, 100), Some(first));
+        // Outage, no interval
+        assert_eq!(inventory.seen(DeviceKind::Sensor, 7, second, false, Some(600), 700), Some(first));
+        inventory.sensor_error(7, "S_CHECKSUM");
+        inventory.sensor_error(7, "S_CHECKSUM");
+
+        let health = inventory.health(&registry, 750);
+        assert_eq!(health.len(), 2);
+        let sensor = &health[0];
+        assert!(sensor.available && !sensor.configured);
+        assert_eq!((sensor.first_seen, sensor.last_seen, sensor.restarts, sensor.offline_sec, sensor.messages), (Some(0), Some(700), 1, 600, 4));
+        assert_eq!(sensor.addresses.iter().map(|used| used.address).collect::<Vec<_>>(), vec![first, second]);
+        assert_eq!((sensor.interval_sec, sensor.jitter_sec), (Some(50.0), Some(10.0)));
+        assert_eq!(sensor.errors.get("S_CHECKSUM"), Some(&2));
+        // Configured but never heard from
+        let relay = &health[1];
+        assert!(relay.configured && !relay.available && relay.last_seen.is_none());
+        assert_eq!(relay.room.as_deref(), Some("bedroom"));
+    }
+}
@@ -0,0 +1,119 @@
+use chrono::{DateTime, Local};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::devices::DeviceKind;
+
+// Templates of the reply to format_diag requests, shown on the devices' displays.
+// Placeholders are written as {name}, trailing spaces of empty ones are dropped.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiagConfig {
+    // First line, left out when empty: {time}
+    pub header: String,
+    // One line per room: {room} {temp} {target} {relay} {mode}
+    pub room: String,
+    // One line per device that stopped reporting: {room} {kind} {id}
+    pub failure: String,
+    // Lines are cut to this many characters, 0 keeps them whole
+    pub width: usize,
+    // Short room names for small displays
+    pub names: HashMap<String, String>,
+}
+
+impl Default for DiagConfig {
+    fn default() -> Self {
+        DiagConfig {
+            header: String::new(),
+            room: "{room}: {temp}/{target} {relay} {mode}".to_string(),
+            failure: "FAIL: {room} {kind}".to_string(),
+            width: 0,
+            names: HashMap::new(),
+        }
+    }
+}
+
+pub struct RoomDiag {
+    pub room: String,
+    pub temp: Option<f64>,
+    pub target: f64,
+    // None before the relay reported
+    pub relay_on: Option<bool>,
+    // Disabled, away, boost, ... in order of importance
+    pub modes: Vec<String>,
+}
+
+pub struct FailedDevice {
+    pub room: String,
+    pub kind: DeviceKind,
+    pub id: u32,
+}
+
+impl DiagConfig {
+    pub fn render(&self, time: DateTime<Local>, rooms: &[RoomDiag], failures: &[FailedDevice]) -> String {
+        let mut lines = Vec::new();
+        if !self.header.is_empty() {
+            lines.push(fill(&self.header, &[("time", time.format("%H:%M").to_string())]));
+        }
+        for room in rooms {
+            lines.push(fill(&self.room, &[
+                ("room", self.name(&room.room).to_string()),
+                ("temp", room.temp.map_or_else(|| "N/A".to_string(), |temp| format!("{:.1}", temp))),
+                ("target", format!("{:.1}", room.target)),
+                ("relay", room.relay_on.map_or("-", |on| if on { "ON" } else { "OFF" }).to_string()),
+                ("mode", room.modes.join(",")),
+            ]));
+        }
+        for failure in failures {
+            lines.push(fill(&self.failure, &[
+                ("room", self.name(&failure.room).to_string()),
+                ("kind", failure.kind.name().to_string()),
+                ("id", failure.id.to_string()),
+            ]));
+        }
+        lines.into_iter()
+            .map(|line| if self.width > 0 { line.chars().take(self.width).collect() } else { line })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    fn name<'a>(&'a self, room: &'a str) -> &'a str {
+        self.names.get(room).map_or(room, String::as_str)
+    }
+}
+
+fn fill(template: &str, values: &[(&str, String)]) -> String {
+    let mut line = template.to_string();
+    for (name, value) in values {
+        line = line.replace(&format!("{{{}}}", name), value);
+    }
+    line.trim_end().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn templates() {
+        let rooms = [
+            RoomDiag { room: "bedroom".to_string(), temp: Some(20.04), target: 21.0, relay_on: Some(true), modes: vec![] },
+            RoomDiag { room: "kids_bedroom".to_string(), temp: None, target: 19.5, relay_on: None, modes: vec!["disabled".to_string()] },
+        ];
+        let failures = [FailedDevice { room: "kids_bedroom".to_string(), kind: DeviceKind::Sensor, id: 2 }];
+        let time = Local.with_ymd_and_hms(2024, 1, 1, 7, 5, 0).unwrap();
+
+        assert_eq!(DiagConfig::default().render(time, &rooms, &failures),
+            "bedroom: 20.0/21.0 ON\nkids_bedroom: N/A/19.5 - disabled\nFAIL: kids_bedroom sensor");
+
+        let config: DiagConfig = toml::from_str(r#"
+            header = "{time}"
+            room = "{room} {temp} {relay}"
+            failure = "!{room} {kind}{id}"
+            width = 10
+            names = { bedroom = "Bed", kids_bedroom = "Kids" }
+        "#).unwrap();
+        assert_eq!(config.render(time, &rooms, &failures), "07:05\nBed 20.0 O\nKids N/A -\n!Kids sens");
+    }
+}
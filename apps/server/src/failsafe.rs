@@ -0,0 +1,86 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+
+#[derive(Deserialize, Clone, Debug, PartialEq, Default)]
+#[serde(tag = "mode", rename_all = "snake_case", deny_unknown_fields)]
+pub enum FailsafeMode {
+    // Relay stays OFF until the sensor reports again
+    #[default]
+    Off,
+    // Heat for `on_minutes` out of every `period_minutes`
+    DutyCycle { on_minutes: u32, period_minutes: u32 },
+    // Run the room's own strategy on a neighbouring room's temperature.
+    // Falls back to OFF while that sensor is silent too.
+    FollowRoom { room: String },
+}
+
+impl FailsafeMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FailsafeMode::Off => "off",
+            FailsafeMode::DutyCycle { .. } => "duty_cycle",
+            FailsafeMode::FollowRoom { .. } => "follow_room",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FailsafeConfig {
+    // Sensor silence after which the failsafe takes over the relay
+    pub timeout_sec: i64,
+    // How often the control task looks for silent sensors
+    pub check_interval_sec: u64,
+    // Failsafe per room name, rooms not listed use OFF
+    pub rooms: HashMap<String, FailsafeMode>,
+}
+
+impl Default for FailsafeConfig {
+    fn default() -> Self {
+        FailsafeConfig { timeout_sec: 300, check_interval_sec: 30, rooms: HashMap::new() }
+    }
+}
+
+impl FailsafeConfig {
+    pub fn mode(&self, room: &str) -> FailsafeMode {
+        self.rooms.get(room).cloned().unwrap_or_default()
+    }
+}
+
+// Heater state for a duty cycle, periods are aligned to the unix epoch
+pub fn duty_cycle_on(on_minutes: u32, period_minutes: u32, timestamp: i64) -> bool {
+    if period_minutes == 0 {
+        return false;
+    }
+    let period = period_minutes as i64 * 60;
+    timestamp.rem_euclid(period) < on_minutes as i64 * 60
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_modes() {
+        let config: FailsafeConfig = toml::from_str(r#"
+            timeout_sec = 600
+            [rooms]
+            bedroom = { mode = "duty_cycle", on_minutes = 10, period_minutes = 30 }
+            kids_bedroom = { mode = "follow_room", room = "bedroom" }
+        "#).unwrap();
+        assert_eq!(config.timeout_sec, 600);
+        assert_eq!(config.mode("bedroom"), FailsafeMode::DutyCycle { on_minutes: 10, period_minutes: 30 });
+        assert_eq!(config.mode("kids_bedroom"), FailsafeMode::FollowRoom { room: "bedroom".to_string() });
+        assert_eq!(config.mode("irina"), FailsafeMode::Off);
+    }
+
+    #[test]
+    fn duty_cycle() {
+        assert!(duty_cycle_on(10, 30, 0));
+        assert!(duty_cycle_on(10, 30, 599));
+        assert!(!duty_cycle_on(10, 30, 600));
+        assert!(!duty_cycle_on(10, 30, 1799));
+        assert!(duty_cycle_on(10, 30, 1800));
+        assert!(!duty_cycle_on(10, 0, 0));
+    }
+}
@@ -0,0 +1,163 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FaultConfig {
+    pub enabled: bool,
+    // Heater confirmed ON this long must raise the temperature by at least `min_rise`
+    pub no_rise_min: u32,
+    pub min_rise: f64,
+    // Relay reporting a state other than the commanded one for this long
+    pub mismatch_sec: i64,
+}
+
+impl Default for FaultConfig {
+    fn default() -> Self {
+        FaultConfig { enabled: true, no_rise_min: 60, min_rise: 0.2, mismatch_sec: 300 }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum HeaterFault {
+    // Relay confirmed ON for `on_min` minutes, the temperature rose only by `rise`.
+    // Heater unplugged or broken.
+    NoTemperatureRise { on_min: f64, rise: f64 },
+    // Relay keeps reporting `reported_on` although `commanded_on` was sent
+    RelayMismatch { commanded_on: bool, reported_on: bool, since: i64 },
+}
+
+impl HeaterFault {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            HeaterFault::NoTemperatureRise { .. } => "no_temperature_rise",
+            HeaterFault::RelayMismatch { .. } => "relay_mismatch",
+        }
+    }
+}
+
+// Changes since the previous check, for logging
+#[derive(Default)]
+pub struct FaultChanges {
+    pub raised: Vec<HeaterFault>,
+    pub cleared: Vec<&'static str>,
+}
+
+// Watches one room's relay reports and temperature
+#[derive(Default)]
+pub struct FaultDetector {
+    // Relay confirmed ON since, and the lowest temperature since then
+    on_since: Option<i64>,
+    min_temp_since_on: Option<f64>,
+    last_temp: Option<f64>,
+    mismatch: Option<(bool, bool, i64)>,
+    faults: Vec<HeaterFault>,
+}
+
+impl FaultDetector {
+    pub fn relay_report(&mut self, timestamp: i64, reported_on: bool, commanded_on: Option<bool>) {
+        if reported_on {
+            if self.on_since.is_none() {
+                self.on_since = Some(timestamp);
+                self.min_temp_since_on = self.last_temp;
+            }
+        } else {
+            self.on_since = None;
+            self.min_temp_since_on = None;
+        }
+
+        self.mismatch = match commanded_on {
+            Some(commanded_on) if commanded_on != reported_on => {
+                let since = self.mismatch
+                    .filter(|&(commanded, reported, _)| commanded == commanded_on && reported == reported_on)
+                    .map_or(timestamp, |(_, _, since)| since);
+                Some((commanded_on, reported_on, since))
+            }
+            _ => None,
+        };
+    }
+
+    pub fn temperature(&mut self, temp: f64) {
+        self.last_temp = Some(temp);
+        if self.on_since.is_some() {
+            self.min_temp_since_on = Some(self.min_temp_since_on.map_or(temp, |min| min.min(temp)));
+        }
+    }
+
+    pub fn check(&mut self, config: &FaultConfig, timestamp: i64) -> FaultChanges {
+        let mut faults = Vec::new();
+        if config.enabled {
+            if let (Some(on_since), Some(min_temp), Some(temp)) = (self.on_since, self.min_temp_since_on, self.last_temp) {
+                let on_min = (timestamp - on_since) as f64 / 60.0;
+                let rise = temp - min_temp;
+                if on_min >= config.no_rise_min as f64 && rise < config.min_rise {
+                    faults.push(HeaterFault::NoTemperatureRise { on_min, rise });
+                }
+            }
+            if let Some((commanded_on, reported_on, since)) = self.mismatch {
+                if timestamp - since >= config.mismatch_sec {
+                    faults.push(HeaterFault::RelayMismatch { commanded_on, reported_on, since });
+                }
+            }
+        }
+
+        let changes = FaultChanges {
+            raised: faults.iter()
+                .filter(|fault| !self.faults.iter().any(|old| old.type_name() == fault.type_name()))
+                .cloned()
+                .collect(),
+            cleared: self.faults.iter()
+                .filter(|old| !faults.iter().any(|fault| fault.type_name() == old.type_name()))
+                .map(HeaterFault::type_name)
+                .collect(),
+        };
+        self.faults = faults;
+        changes
+    }
+
+    pub fn faults(&self) -> &[HeaterFault] {
+        &self.faults
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_temperature_rise() {
+        let config = FaultConfig::default();
+        let mut detector = FaultDetector::default();
+        detector.temperature(19.0);
+        detector.relay_report(0, true, Some(true));
+        detector.temperature(18.8);
+        assert!(detector.check(&config, 1800).raised.is_empty());
+
+        detector.temperature(18.9);
+        let changes = detector.check(&config, 3600);
+        assert_eq!(changes.raised.len(), 1);
+        assert_eq!(changes.raised[0].type_name(), "no_temperature_rise");
+        // Reported once
+        assert!(detector.check(&config, 3660).raised.is_empty());
+
+        // Rise measured from the lowest temperature since the heater went on
+        detector.temperature(19.1);
+        assert_eq!(detector.check(&config, 3720).cleared, vec!["no_temperature_rise"]);
+        assert!(detector.faults().is_empty());
+    }
+
+    #[test]
+    fn relay_mismatch() {
+        let config = FaultConfig::default();
+        let mut detector = FaultDetector::default();
+        detector.relay_report(0, false, Some(true));
+        detector.relay_report(200, false, Some(true));
+        assert!(detector.check(&config, 200).raised.is_empty());
+        detector.relay_report(300, false, Some(true));
+        assert_eq!(detector.check(&config, 300).raised,
+            vec![HeaterFault::RelayMismatch { commanded_on: true, reported_on: false, since: 0 }]);
+
+        detector.relay_report(360, true, Some(true));
+        assert_eq!(detector.check(&config, 360).cleared, vec!["relay_mismatch"]);
+    }
+}
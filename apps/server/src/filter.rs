@@ -0,0 +1,209 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SensorFilterConfig {
+    pub enabled: bool,
+    // Readings outside these ranges are impossible indoors
+    pub min_temp: f64,
+    pub max_temp: f64,
+    pub min_humidity: f64,
+    pub max_humidity: f64,
+    // Largest believable change per minute against the last accepted reading
+    pub max_rate_per_min: f64,
+    // Readings further than `outlier_temp` from the median of the last `median_window` readings
+    pub median_window: usize,
+    pub outlier_temp: f64,
+    // Per sensor name overrides, the room name for a room's own sensor
+    pub rooms: HashMap<String, RoomFilter>,
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct RoomFilter {
+    pub min_temp: Option<f64>,
+    pub max_temp: Option<f64>,
+    pub max_rate_per_min: Option<f64>,
+    pub outlier_temp: Option<f64>,
+}
+
+impl Default for SensorFilterConfig {
+    fn default() -> Self {
+        SensorFilterConfig {
+            enabled: true,
+            min_temp: -10.0,
+            max_temp: 50.0,
+            min_humidity: 0.0,
+            max_humidity: 100.0,
+            max_rate_per_min: 1.0,
+            median_window: 5,
+            outlier_temp: 1.5,
+            rooms: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FilterLimits {
+    pub min_temp: f64,
+    pub max_temp: f64,
+    pub min_humidity: f64,
+    pub max_humidity: f64,
+    pub max_rate_per_min: f64,
+    pub median_window: usize,
+    pub outlier_temp: f64,
+}
+
+impl SensorFilterConfig {
+    pub fn limits(&self, room: &str) -> FilterLimits {
+        let room = self.rooms.get(room).copied().unwrap_or_default();
+        FilterLimits {
+            min_temp: room.min_temp.unwrap_or(self.min_temp),
+            max_temp: room.max_temp.unwrap_or(self.max_temp),
+            min_humidity: self.min_humidity,
+            max_humidity: self.max_humidity,
+            max_rate_per_min: room.max_rate_per_min.unwrap_or(self.max_rate_per_min),
+            median_window: self.median_window,
+            outlier_temp: room.outlier_temp.unwrap_or(self.outlier_temp),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RejectReason {
+    TemperatureRange,
+    HumidityRange,
+    RateOfChange,
+    Outlier,
+}
+
+impl RejectReason {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RejectReason::TemperatureRange => "temperature_range",
+            RejectReason::HumidityRange => "humidity_range",
+            RejectReason::RateOfChange => "rate_of_change",
+            RejectReason::Outlier => "outlier",
+        }
+    }
+}
+
+// Readings of one sensor, timestamps are unix seconds
+#[derive(Default)]
+pub struct SensorFilter {
+    // Last readings within the plausible range, accepted or not
+    recent: VecDeque<f64>,
+    last_accepted: Option<(i64, f64)>,
+    // Plausible readings rejected in a row, that many mean the level really changed
+    consecutive_rejects: usize,
+    rejected: u64,
+}
+
+impl SensorFilter {
+    // Returns the reason when the reading must not be acted on
+    pub fn check(&mut self, limits: &FilterLimits, timestamp: i64, temp: f64, humidity: f64) -> Option<RejectReason> {
+        let reason = self.reason(limits, timestamp, temp, humidity);
+        match reason {
+            Some(RejectReason::TemperatureRange | RejectReason::HumidityRange) => {}
+            Some(_) if self.consecutive_rejects + 1 < limits.median_window.max(1) => {
+                self.push_recent(limits, temp);
+                self.consecutive_rejects += 1;
+            }
+            _ => {
+                // Plausible, or the same new level for a whole window
+                self.push_recent(limits, temp);
+                self.consecutive_rejects = 0;
+                self.last_accepted = Some((timestamp, temp));
+                return None;
+            }
+        }
+        self.rejected += 1;
+        reason
+    }
+
+    // Readings rejected since startup
+    pub fn rejected(&self) -> u64 {
+        self.rejected
+    }
+
+    fn reason(&self, limits: &FilterLimits, timestamp: i64, temp: f64, humidity: f64) -> Option<RejectReason> {
+        if !(limits.min_temp..=limits.max_temp).contains(&temp) {
+            return Some(RejectReason::TemperatureRange);
+        }
+        if !(limits.min_humidity..=limits.max_humidity).contains(&humidity) {
+            return Some(RejectReason::HumidityRange);
+        }
+        // Needs a few readings for a meaningful median
+        if self.recent.len() >= 3 && (temp - median(&self.recent)).abs() > limits.outlier_temp {
+            return Some(RejectReason::Outlier);
+        }
+        if let Some((last_timestamp, last_temp)) = self.last_accepted {
+            // At least a minute, readings come in 0.1 steps
+            let minutes = ((timestamp - last_timestamp) as f64 / 60.0).max(1.0);
+            if (temp - last_temp).abs() > limits.max_rate_per_min * minutes {
+                return Some(RejectReason::RateOfChange);
+            }
+        }
+        None
+    }
+
+    fn push_recent(&mut self, limits: &FilterLimits, temp: f64) {
+        self.recent.push_back(temp);
+        while self.recent.len() > limits.median_window.max(1) {
+            self.recent.pop_front();
+        }
+    }
+}
+
+fn median(values: &VecDeque<f64>) -> f64 {
+    let mut sorted: Vec<f64> = values.iter().copied().collect();
+    sorted.sort_by(f64::total_cmp);
+    let middle = sorted.len() / 2;
+    if sorted.len().is_multiple_of(2) {
+        (sorted[middle - 1] + sorted[middle]) / 2.0
+    } else {
+        sorted[middle]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_spikes_and_implausible_readings() {
+        let limits = SensorFilterConfig::default().limits("bedroom");
+        let mut filter = SensorFilter::default();
+        for (minute, temp) in [20.0, 20.1, 20.1, 20.2].into_iter().enumerate() {
+            assert_eq!(filter.check(&limits, minute as i64 * 60, temp, 45.0), None);
+        }
+        // Single misread
+        assert_eq!(filter.check(&limits, 240, 27.3, 45.0), Some(RejectReason::Outlier));
+        assert_eq!(filter.check(&limits, 300, 20.2, 45.0), None);
+        assert_eq!(filter.check(&limits, 360, -40.0, 45.0), Some(RejectReason::TemperatureRange));
+        assert_eq!(filter.check(&limits, 420, 20.3, 120.0), Some(RejectReason::HumidityRange));
+        assert_eq!(filter.rejected(), 3);
+
+        // Too fast for the time since the last accepted reading, without a median yet
+        let mut filter = SensorFilter::default();
+        assert_eq!(filter.check(&limits, 0, 20.0, 45.0), None);
+        assert_eq!(filter.check(&limits, 60, 21.5, 45.0), Some(RejectReason::RateOfChange));
+        assert_eq!(filter.check(&limits, 240, 21.5, 45.0), None);
+    }
+
+    #[test]
+    fn accepts_a_new_level() {
+        let limits = SensorFilterConfig::default().limits("bedroom");
+        let mut filter = SensorFilter::default();
+        for minute in 0..5 {
+            assert_eq!(filter.check(&limits, minute * 60, 20.0, 45.0), None);
+        }
+        // Sensor moved next to the radiator, 3 degrees within seconds
+        let results: Vec<_> = (0..5).map(|i| filter.check(&limits, 300 + i * 10, 23.0, 45.0)).collect();
+        assert!(results[0].is_some());
+        assert_eq!(results.iter().filter(|result| result.is_some()).count(), 4);
+        assert_eq!(results[4], None);
+    }
+}
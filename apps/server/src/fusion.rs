@@ -0,0 +1,162 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SensorFusionConfig {
+    // Readings older than this are left out, the room runs on the other sensors
+    pub stale_sec: i64,
+    // Sensors by room name, rooms not listed use their sensors from [devices]
+    pub rooms: HashMap<String, RoomSensors>,
+}
+
+impl Default for SensorFusionConfig {
+    fn default() -> Self {
+        SensorFusionConfig { stale_sec: 300, rooms: HashMap::new() }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FusionMode {
+    #[default]
+    Average,
+    // Average with `weights`, one per sensor
+    Weighted,
+    // Coldest sensor, so no corner of the room stays cold
+    Min,
+    // First sensor in the list that still reports
+    Primary,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RoomSensors {
+    // Sensor device ids, in order of preference
+    pub sensors: Vec<u32>,
+    #[serde(default)]
+    pub mode: FusionMode,
+    #[serde(default)]
+    pub weights: Vec<f64>,
+}
+
+impl SensorFusionConfig {
+    pub fn room(&self, room: &str, own_sensors: Vec<u32>) -> RoomSensors {
+        self.rooms.get(room).cloned()
+            .unwrap_or_else(|| RoomSensors { sensors: own_sensors, mode: FusionMode::Average, weights: Vec::new() })
+    }
+}
+
+// Calibrated reading of one sensor
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct SensorReading {
+    pub timestamp: i64,
+    pub temperature: f64,
+    pub humidity: f64,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Fused {
+    pub temperature: f64,
+    pub humidity: f64,
+    // Sensors that contributed
+    pub sensors: Vec<u32>,
+}
+
+impl RoomSensors {
+    fn fresh<'a>(&'a self, readings: &'a HashMap<u32, SensorReading>, now: i64, stale_sec: i64)
+        -> impl Iterator<Item = (usize, u32, &'a SensorReading)> + 'a {
+        self.sensors.iter().enumerate().filter_map(move |(i, sensor)| {
+            readings.get(sensor)
+                .filter(|reading| now - reading.timestamp <= stale_sec)
+                .map(|reading| (i, *sensor, reading))
+        })
+    }
+
+    // The first sensor that still reports, its readings trigger the room's control
+    pub fn driver(&self, readings: &HashMap<u32, SensorReading>, now: i64, stale_sec: i64) -> Option<u32> {
+        self.fresh(readings, now, stale_sec).next().map(|(_, sensor, _)| sensor)
+    }
+
+    pub fn fuse(&self, readings: &HashMap<u32, SensorReading>, now: i64, stale_sec: i64) -> Option<Fused> {
+        let fresh: Vec<_> = self.fresh(readings, now, stale_sec).collect();
+        if fresh.is_empty() {
+            return None;
+        }
+        let average = |weight: &dyn Fn(usize) -> f64| {
+            let total: f64 = fresh.iter().map(|(i, _, _)| weight(*i)).sum();
+            let temperature = fresh.iter().map(|(i, _, reading)| weight(*i) * reading.temperature).sum::<f64>() / total;
+            let humidity = fresh.iter().map(|(i, _, reading)| weight(*i) * reading.humidity).sum::<f64>() / total;
+            (temperature, humidity, fresh.iter().map(|(_, sensor, _)| *sensor).collect())
+        };
+        let (temperature, humidity, sensors) = match self.mode {
+            FusionMode::Average => average(&|_| 1.0),
+            FusionMode::Weighted => {
+                let weight = |i: usize| self.weights.get(i).copied().unwrap_or(1.0).max(0.0);
+                // Only zero weights left, e.g. the weighted sensor is stale
+                if fresh.iter().map(|(i, _, _)| weight(*i)).sum::<f64>() > 0.0 {
+                    average(&weight)
+                } else {
+                    average(&|_| 1.0)
+                }
+            }
+            FusionMode::Min => {
+                let (_, sensor, reading) = fresh.iter()
+                    .min_by(|(_, _, a), (_, _, b)| a.temperature.total_cmp(&b.temperature))
+                    .unwrap();
+                (reading.temperature, reading.humidity, vec![*sensor])
+            }
+            FusionMode::Primary => {
+                let (_, sensor, reading) = fresh[0];
+                (reading.temperature, reading.humidity, vec![sensor])
+            }
+        };
+        Some(Fused { temperature, humidity, sensors })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reading(timestamp: i64, temperature: f64) -> SensorReading {
+        SensorReading { timestamp, temperature, humidity: 40.0 }
+    }
+
+    #[test]
+    fn modes_and_fallback() {
+        let config: SensorFusionConfig = toml::from_str(r#"
+            [rooms]
+            bedroom = { sensors = [0, 5], mode = "weighted", weights = [3.0, 1.0] }
+            kids_bedroom = { sensors = [2, 6], mode = "min" }
+        "#).unwrap();
+        let readings = HashMap::from([(0, reading(100, 20.0)), (5, reading(300, 22.0)), (2, reading(100, 21.0)), (6, reading(0, 18.0))]);
+
+        let bedroom = config.room("bedroom", vec![0]);
+        assert_eq!(bedroom.fuse(&readings, 100, 300).unwrap().temperature, 20.5);
+        assert_eq!(bedroom.driver(&readings, 100, 300), Some(0));
+        // Sensor 0 stale, the room runs on sensor 5
+        assert_eq!(bedroom.fuse(&readings, 450, 300).unwrap(), Fused { temperature: 22.0, humidity: 40.0, sensors: vec![5] });
+        assert_eq!(bedroom.driver(&readings, 450, 300), Some(5));
+        assert_eq!(bedroom.fuse(&readings, 1000, 300), None);
+
+        let kids = config.room("kids_bedroom", vec![2]);
+        assert_eq!(kids.fuse(&readings, 100, 300).unwrap().temperature, 18.0);
+        assert_eq!(kids.fuse(&readings, 301, 300).unwrap().temperature, 21.0);
+        // Unlisted rooms use their own sensors
+        assert_eq!(config.room("irina", vec![1, 7]).sensors, vec![1, 7]);
+    }
+
+    #[test]
+    fn zero_weights() {
+        let config: SensorFusionConfig = toml::from_str(r#"
+            [rooms]
+            bedroom = { sensors = [0, 5, 6], mode = "weighted", weights = [1.0, 0.0, -1.0] }
+        "#).unwrap();
+        let readings = HashMap::from([(0, reading(0, 18.0)), (5, reading(300, 20.0)), (6, reading(300, 22.0))]);
+        let bedroom = config.room("bedroom", vec![0]);
+        assert_eq!(bedroom.fuse(&readings, 300, 300).unwrap().temperature, 18.0);
+        // Sensor 0 stale, the rest is averaged instead of dividing by zero
+        assert_eq!(bedroom.fuse(&readings, 400, 300).unwrap().temperature, 21.0);
+    }
+}
@@ -0,0 +1,48 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::EnvFilter;
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    // One human readable line per event
+    #[default]
+    Text,
+    // One JSON object per line, for log shippers
+    Json,
+    // Native journald fields, when running under systemd
+    Journald,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub format: LogFormat,
+    // EnvFilter directives, e.g. "info" or "info,temperature_server=debug".
+    // RUST_LOG takes precedence when set.
+    pub level: String,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig { format: LogFormat::Text, level: "info".to_string() }
+    }
+}
+
+pub fn init(config: &LogConfig) -> Result<()> {
+    let filter = EnvFilter::try_from_default_env()
+        .or_else(|_| EnvFilter::try_new(&config.level))
+        .with_context(|| format!("Invalid log level: {}", config.level))?;
+    let registry = tracing_subscriber::registry().with(filter);
+
+    match config.format {
+        LogFormat::Text => registry.with(tracing_subscriber::fmt::layer().with_target(false)).init(),
+        LogFormat::Json => registry.with(tracing_subscriber::fmt::layer().json().flatten_event(true)).init(),
+        LogFormat::Journald => {
+            let journald = tracing_journald::layer().context("Failed to connect to journald")?;
+            registry.with(journald).init()
+        }
+    }
+    Ok(())
+}
@@ -1,49 +1,190 @@
+pub mod alert;
+pub mod audit;
+pub mod auth;
+pub mod button;
+pub mod calibration;
+pub mod config;
+pub mod control;
+pub mod demand;
+pub mod devices;
+pub mod diag;
+pub mod failsafe;
+pub mod fault;
+pub mod filter;
+pub mod fusion;
+pub mod logging;
 pub mod pwm;
+pub mod relay_guard;
+pub mod safety;
 pub mod schedule;
+pub mod shutdown;
+pub mod static_files;
+pub mod systemd;
 pub mod web;
+pub mod window;
 
 use anyhow::{Context, Result};
 use chrono::{DateTime, Local, Timelike};
-use std::collections::HashMap;
+use clap::Parser;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fs::{File, rename};
-use std::io::{Write, stdout};
-use std::net::{SocketAddr, UdpSocket};
+use std::io::Write;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{RwLock, broadcast, mpsc, oneshot, watch};
+use tracing::{debug, error, info, warn};
 use crate::schedule::INTERPOLATE_INTERVALS;
-use crate::web::{ServerState, create_web_server, TemperaturePoint};
+use crate::web::{PendingSwitch, ServerState, SensorState, StatusEvent, WebCommand, WebState, create_web_server, publish, TemperaturePoint, EVENTS_CAPACITY};
 
 // These are from the temperature_protocol crate
-use temperature_protocol::fragment_combiner::{FragmentCombiner, MessageHandler};
+use temperature_protocol::fragment_combiner::{fragment, FragmentCombiner, MessageHandler, MAX_UDP};
 use temperature_protocol::protos::generated::dev::{
-    DeviceMessage, DeviceInfo, SensorReport, RelayReport, SensorError,
+    DeviceMessage, DeviceInfo, SensorReport, RelayReport, SensorError, ButtonState,
 };
-use temperature_protocol::relay::set_relay;
-
-use crate::pwm::{Control, SimpleControl, PWMControl};
+use temperature_protocol::relay::UdpSender;
+
+use crate::pwm::{Control, Decision, SimpleControl, PWMControl};
+use crate::auth::Auth;
+use crate::audit::{AuditKind, AuditLog};
+use crate::button::RoomMode;
+use crate::calibration::{Calibrator, SensorCalibration};
+use crate::config::Config;
+use crate::demand::{HeatDemand, HeatDemandState};
+use crate::devices::{AVAILABLE_SEC, DeviceInventory, DeviceKind, DeviceRegistry, DevicesConfig, RelayDevice, SensorDevice};
+use crate::diag::{FailedDevice, RoomDiag};
+use crate::failsafe::{duty_cycle_on, FailsafeMode};
+use crate::safety::SafetyIntervention;
+use crate::shutdown::{SavedRoom, SavedState};
+use crate::systemd::{Liveness, SystemdConfig};
+use crate::relay_guard::{ProtectionReason, RelayCommand, RelayGuard};
+use crate::window::WindowDetector;
+use crate::fault::FaultDetector;
+use crate::filter::SensorFilter;
+use crate::fusion::{Fused, RoomSensors, SensorReading};
+use crate::alert::{AlertKind, AlertManager};
+
+#[derive(Parser)]
+#[command(about = "Temperature control server")]
+struct Args {
+    /// Path to the TOML configuration file
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Serve the web UI from this directory, e.g. temperature-react-ui/dist during UI development
+    #[arg(long)]
+    static_dir: Option<PathBuf>,
+
+    /// Read a password from stdin, print its hash for the config and exit
+    #[arg(long)]
+    hash_password: bool,
+
+    /// Generate an API token, print it with its hash for the config and exit
+    #[arg(long)]
+    new_token: bool,
+}
 
 // --- Constants mimicking C++ globals ---
+// Relays of the default devices, used without a [devices] section
 const RELAYS: [&str; 3] = [
     "esp8266-relay0.local", // ID 0: bedroom
     "esp8266-relay1.local", // ID 1: irina
     "esp8266-relay2.local", // ID 2: kids room
 ];
 
+// Temperature offsets of sensors without a [calibration.sensors] entry
 const CORRECTION: [f64; 3] = [
     -0.0, // ID 0
     -0.9, // ID 1
     -0.6, // ID 2
 ];
 
-// For diagnostic staleness checks
-const BEDROOM_SENSOR_EXPECTED_IP: &str = "192.168.0.200";
-const BEDROOM_RELAY_EXPECTED_IP: &str = "192.168.0.210"; // This is esp8266-relay0.local
-const KIDS_SENSOR_EXPECTED_IP: &str = "192.168.0.202";
-const KIDS_RELAY_EXPECTED_IP: &str = "192.168.0.212";   // This is esp8266-relay2.local
+// Addresses of the default devices, identify messages without an id
+const SENSOR_ADDRESSES: [Option<&str>; 3] = [Some("192.168.0.200"), None, Some("192.168.0.202")];
+const RELAY_ADDRESSES: [Option<&str>; 3] = [Some("192.168.0.210"), None, Some("192.168.0.212")];
 
 // Path for Netdata files
 const NETDATA_PATH_PREFIX: &str = "/var/lib/temperature";
 
+// Room names by device ID, as used in logs and the web API
+const ROOM_NAMES: [&str; 3] = [
+    "bedroom",      // ID 0
+    "irina",        // ID 1
+    "kids_bedroom", // ID 2
+];
+
+fn room_name(device_id: u32) -> &'static str {
+    ROOM_NAMES.get(device_id as usize).copied().unwrap_or("unmanaged")
+}
+
+fn room_index(room: &str) -> Option<u32> {
+    ROOM_NAMES.iter().position(|name| *name == room).map(|id| id as u32)
+}
+
+// Devices without a [devices] section: sensor and relay ids equal the room index
+fn default_devices() -> DevicesConfig {
+    let address = |address: Option<&str>| address.map(|address| address.parse::<IpAddr>().unwrap());
+    DevicesConfig {
+        sensors: ROOM_NAMES.iter().enumerate()
+            .map(|(id, room)| SensorDevice { id: id as u32, room: Some(room.to_string()), address: address(SENSOR_ADDRESSES[id]) })
+            .collect(),
+        relays: ROOM_NAMES.iter().enumerate()
+            .map(|(id, room)| RelayDevice {
+                id: id as u32,
+                room: room.to_string(),
+                host: RELAYS[id].to_string(),
+                address: address(RELAY_ADDRESSES[id]),
+            })
+            .collect(),
+    }
+}
+
+// Keeps log fields readable, e.g. 21.3 instead of 21.299999999999997
+fn round2(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+// Files for the Netdata collector, written atomically by renaming
+fn write_netdata(device_id: u32, temp: f64, target_temp: f64, humidity: f64) {
+    let tmp_file_path_str = format!("{}/new{}", NETDATA_PATH_PREFIX, device_id);
+    let current_file_path_str = format!("{}/current{}", NETDATA_PATH_PREFIX, device_id);
+    let humidity_file_path_str = format!("{}/humidity{}", NETDATA_PATH_PREFIX, device_id);
+
+    // Write temperature and target
+    match File::create(&tmp_file_path_str) {
+        Ok(mut file) => {
+            if let Err(e) = writeln!(file, "SET temperature = {:.0}", temp * 10.0) {
+                 warn!("Error writing temperature to {}: {}", tmp_file_path_str, e);
+            }
+            if let Err(e) = writeln!(file, "SET target = {:.0}", target_temp * 10.0) {
+                warn!("Error writing target to {}: {}", tmp_file_path_str, e);
+            }
+            // C++ dprintf, then close, then rename. Rust write, then rename.
+            drop(file); // Ensure file is closed before rename
+            if let Err(e) = rename(&tmp_file_path_str, &current_file_path_str) {
+                warn!("Error renaming {} to {}: {}", tmp_file_path_str, current_file_path_str, e);
+            }
+        }
+        Err(e) => warn!("Error creating {}: {}", tmp_file_path_str, e),
+    }
+
+    // Write humidity
+    match File::create(&tmp_file_path_str) { // Reuse tmp_file_path_str for humidity
+        Ok(mut file) => {
+             if let Err(e) = writeln!(file, "SET humidity = {:.0}", humidity * 10.0) {
+                 warn!("Error writing humidity to {}: {}", tmp_file_path_str, e);
+             }
+             drop(file);
+             if let Err(e) = rename(&tmp_file_path_str, &humidity_file_path_str) {
+                warn!("Error renaming {} to {}: {}", tmp_file_path_str, humidity_file_path_str, e);
+             }
+        }
+        Err(e) => warn!("Error creating {}: {}", tmp_file_path_str, e),
+    }
+}
+
 
 fn linear_rust(val_start: f64, val_end: f64, x_start: f64, x_end: f64, x_target: f64) -> f64 {
     if x_end == x_start {
@@ -118,97 +259,188 @@ impl Default for RelayConfirmationState {
     }
 }
 
+// State a room asks its relay for, before safety limits and relay protection
+#[derive(Clone, Copy)]
+struct RelayRequest {
+    on: bool,
+    delay_ms: u32,
+    // Button forced heat, replaces the strategy's decision
+    force_heat: bool,
+    // Disabled or open window, switches off
+    paused: bool,
+    // Set by hand in the web UI, sent without relay protection
+    manual: bool,
+}
+
+// Relay command sent for a room and what adjusted it on the way
+struct RelayOutcome {
+    on: bool,
+    delay_ms: u32,
+    safety: Option<SafetyIntervention>,
+    protection: Option<ProtectionReason>,
+    // No report after the previous command, last reported state
+    unconfirmed: bool,
+    relay_on: bool,
+    // The command reached the socket, failures are logged and audited
+    sent: bool,
+}
+
 struct Server {
-    // Key: Source IP string (e.g., "192.168.0.100")
-    last_message_timestamp: HashMap<String, i64>,
+    // Last message, addresses and statistics of every device
+    inventory: Arc<DeviceInventory>,
     // Key: Device ID (u32)
     last_temp_deci: HashMap<u32, f64>, // Storing as corrected temp
-    // Key: Relay's source IP string (e.g. "192.168.0.210")
-    last_relay_on_status: HashMap<String, bool>,
+    // Key: Room index, state reported by the room's relay
+    last_relay_on_status: HashMap<u32, bool>,
     // Key: Relay hostname (e.g. "esp8266-relay0.local")
     relay_confirmations: HashMap<String, RelayConfirmationState>,
 
-    controls: Vec<Box<dyn Control>>,
+    // Key: Device ID, time of the last report with a temperature
+    last_sensor_report: HashMap<u32, i64>,
+    // Device IDs whose relay is driven by the failsafe
+    in_failsafe: HashSet<u32>,
+    started_at: i64,
+    // Key: Device ID, safety limit currently overriding the strategy
+    safety_active: HashMap<u32, SafetyIntervention>,
+    // Key: Device ID
+    relay_guards: HashMap<u32, RelayGuard>,
+    // Key: Device ID
+    window_detectors: HashMap<u32, WindowDetector>,
+    // Key: Device ID
+    fault_detectors: HashMap<u32, FaultDetector>,
+    // Key: Device ID, time of the last relay report
+    last_relay_report: HashMap<u32, i64>,
+    // Key: Device ID
+    sensor_filters: HashMap<u32, SensorFilter>,
+    // Key: Device ID, last accepted and calibrated reading of each sensor
+    sensor_readings: HashMap<u32, SensorReading>,
+    // Key: Device ID of the room, sensors used for its last temperature
+    fused_sensors: HashMap<u32, Vec<u32>>,
+    // Key: Device ID, times of recent sensor errors
+    sensor_errors: HashMap<u32, VecDeque<i64>>,
+    // Key: Device ID of the room, heating disabled from the web UI until this time
+    disabled_until: HashMap<u32, i64>,
+    // Key: Device ID of the room, mode set with a sensor button
+    room_modes: HashMap<u32, RoomMode>,
+    // Key: Device ID, last reported button state
+    button_states: HashMap<u32, bool>,
+    // Key: Device ID of the room, relay state last commanded, ON means the room calls for heat
+    heat_calls: HashMap<u32, bool>,
+    // Key: Source address of a heat_on message, its value and time
+    external_demand: HashMap<IpAddr, (bool, i64)>,
+    heat_demand: HeatDemand,
+    // Sequence number of fragmented diag replies
+    diag_seq: u8,
+    alerts: AlertManager,
+    calibrator: Arc<Calibrator>,
+    devices: Arc<DeviceRegistry>,
+    // Relay commands, heat_on and diag replies
+    sender: Arc<UdpSender>,
+    // Control related sections, web, auth and audit are taken out by main
+    config: Config,
+
+    controls: Vec<Box<dyn Control + Send + Sync>>,
     web_state: Arc<RwLock<ServerState>>,
-}
-
-#[derive(PartialEq, Debug)]
-enum PrintHeaderStatus {
-    Failure,
-    Ok,
-    HasStatusUpdate,
+    events: broadcast::Sender<StatusEvent>,
+    audit: Arc<AuditLog>,
 }
 
 impl Server {
-    fn new() -> Server {
-        let controls: Vec<Box<dyn Control>> = vec![
+    fn new(audit: Arc<AuditLog>, alerts: AlertManager, calibrator: Arc<Calibrator>, devices: Arc<DeviceRegistry>,
+        inventory: Arc<DeviceInventory>, sender: Arc<UdpSender>, config: Config) -> Server {
+        let controls: Vec<Box<dyn Control + Send + Sync>> = vec![
             Box::new(PWMControl::new(-0.36)),
             Box::new(SimpleControl::new()),
             Box::new(PWMControl::new(-0.36)),
         ];
+        // Rooms with devices are shown in the web API
+        let web_state = ServerState::new(ROOM_NAMES.into_iter().filter(|room| devices.has_room(room)));
 
         Server {
-            last_message_timestamp: HashMap::new(),
+            inventory,
             last_temp_deci: HashMap::new(),
             last_relay_on_status: HashMap::new(),
             relay_confirmations: HashMap::new(),
+            last_sensor_report: HashMap::new(),
+            in_failsafe: HashSet::new(),
+            started_at: Local::now().timestamp(),
+            safety_active: HashMap::new(),
+            relay_guards: HashMap::new(),
+            window_detectors: HashMap::new(),
+            fault_detectors: HashMap::new(),
+            last_relay_report: HashMap::new(),
+            sensor_filters: HashMap::new(),
+            sensor_readings: HashMap::new(),
+            fused_sensors: HashMap::new(),
+            sensor_errors: HashMap::new(),
+            disabled_until: HashMap::new(),
+            room_modes: HashMap::new(),
+            button_states: HashMap::new(),
+            heat_calls: HashMap::new(),
+            external_demand: HashMap::new(),
+            heat_demand: HeatDemand::default(),
+            diag_seq: 0,
+            alerts,
+            calibrator,
+            devices,
+            sender,
+            config,
             controls,
-            web_state: Arc::new(RwLock::new(ServerState::default())),
+            web_state: Arc::new(RwLock::new(web_state)),
+            events: broadcast::channel(EVENTS_CAPACITY).0,
+            audit,
         }
     }
 
-    fn print_header(&self, client_address_str: &str, info: &DeviceInfo) -> PrintHeaderStatus {
-        let device_id = match info.id {
-            Some(id) => id,
-            None => {
-                print!(
-                    "Message without id from {}\n",
-                    client_address_str
-                );
-                let _ = stdout().flush();
-                return PrintHeaderStatus::Failure;
-            }
+    // Logs restarts and offline periods, returns the firmware id or None for unknown devices
+    fn check_header(&mut self, src: SocketAddr, info: &DeviceInfo, kind: DeviceKind) -> Option<u32> {
+        let Some(device_id) = self.devices.identify(kind, info.id, src.ip()) else {
+            warn!(source = %src.ip(), kind = kind.name(), "message without id from an unknown address");
+            return None;
         };
+        let room = self.devices.room_of(kind, device_id).unwrap_or("unmanaged").to_string();
 
-        let current_time = Local::now();
-        // C++ ctime format: "Wed Jun 30 21:49:08 2021"
-        // Rust: "%a %b %e %H:%M:%S %Y"
-        // Note: %e pads with space for single digit day, %d pads with 0. ctime uses space.
-        let formatted_time = current_time.format("%a %b %_d %H:%M:%S %Y").to_string(); // %_d for space padding
-
-        print!("{} [{}]: ", formatted_time, device_id);
+        let now = Local::now().timestamp();
+        if let Some(previous) = self.inventory.seen(kind, device_id, src.ip(), info.started(), info.offline_sec, now) {
+            if previous != src.ip() {
+                info!(device_id, kind = kind.name(), room, previous = %previous, source = %src.ip(), "device address changed");
+            }
+        }
 
-        let mut status = PrintHeaderStatus::Ok;
         if info.started() {
-            print!("(STARTED) ");
-            status = PrintHeaderStatus::HasStatusUpdate;
+            info!(device_id, kind = kind.name(), room, source = %src.ip(), "device started");
+            self.audit.record(AuditKind::DeviceStarted {
+                device_id,
+                source: src.ip().to_string(),
+            });
+            self.alerts.event(AlertKind::DeviceRestarted, &format!("{} {}", room, kind.name()),
+                format!("{} {} (id {}) at {} restarted", room, kind.name(), device_id, src.ip()),
+                now);
         }
         if let Some(offline_sec) = info.offline_sec {
-            print!("(OFFLINE {:.2}m) ", offline_sec as f64 / 60.0);
-            status = PrintHeaderStatus::HasStatusUpdate;
+            warn!(device_id, kind = kind.name(), room, source = %src.ip(),
+                offline_min = round2(offline_sec as f64 / 60.0), "device was offline");
+            self.audit.record(AuditKind::DeviceOffline {
+                device_id,
+                source: src.ip().to_string(),
+                offline_sec,
+            });
         }
-        status
+        Some(device_id)
     }
 
-    async fn update_history(&self, device_id : u32, current_timestamp: i64, temp: f64, target_temp: f64, header_on: bool, is_disabled: bool) -> Result<()> {
+    async fn update_history(&self, device_id : u32, point: TemperaturePoint) -> Result<()> {
         //Update temperature history in web state
         let mut web_state = self.web_state.write().await;
-        let room_state = if device_id == 0 {
-            &mut web_state.bedroom
-        } else if device_id == 2 {
-            &mut web_state.kids_bedroom
-        } else {
+        let room = room_name(device_id);
+        let Some(room_state) = web_state.room_mut(room) else {
             return Ok(());
         };
 
         // Add new temperature point
-        room_state.temperature_history.push(TemperaturePoint {
-            timestamp: current_timestamp,
-            temperature: temp,
-            target: target_temp,
-            heater_on : header_on,
-            is_disabled,
-        });
+        let current_timestamp = point.timestamp;
+        room_state.temperature_history.push(point.clone());
+        publish(&self.events, StatusEvent::Point { room: room.to_string(), point });
 
         // Keep only last 48 hours of data
         let cutout = current_timestamp - 3600 * 48;
@@ -216,97 +448,623 @@ impl Server {
         return Ok(());
     }
 
+    fn is_available(&self, kind: DeviceKind, device_id: u32) -> bool {
+        self.inventory.last_seen(kind, device_id)
+            .is_some_and(|timestamp| Local::now().timestamp() - timestamp < AVAILABLE_SEC)
+    }
+
+    // Any of the room's sensors reports
+    fn sensor_available(&self, room_id: u32) -> bool {
+        self.room_fusion(room_id).sensors.iter().any(|&sensor| self.is_available(DeviceKind::Sensor, sensor))
+    }
+
+    fn relay_available(&self, room_id: u32) -> bool {
+        self.devices.room_relay(room_name(room_id)).is_some_and(|relay| self.is_available(DeviceKind::Relay, relay.id))
+    }
+
+    // Sensors of a room from [sensor_fusion], or the registry's sensors of the room
+    fn room_fusion(&self, room_id: u32) -> RoomSensors {
+        let room = room_name(room_id);
+        self.config.sensor_fusion.room(room, self.devices.room_sensors(room))
+    }
+
+    fn sensor_states(&self, room_id: u32) -> Vec<SensorState> {
+        let now = Local::now().timestamp();
+        self.room_fusion(room_id).sensors.into_iter()
+            .map(|device_id| {
+                let reading = self.sensor_readings.get(&device_id);
+                SensorState {
+                    device_id,
+                    temperature: reading.map(|reading| reading.temperature),
+                    humidity: reading.map(|reading| reading.humidity),
+                    timestamp: reading.map(|reading| reading.timestamp),
+                    stale: reading.is_none_or(|reading| now - reading.timestamp > self.config.sensor_fusion.stale_sec),
+                    rejected_readings: self.sensor_filters.get(&device_id).map_or(0, SensorFilter::rejected),
+                }
+            })
+            .collect()
+    }
+
+    fn pending_switch(&self, device_id: u32) -> Option<PendingSwitch> {
+        let guard = self.relay_guards.get(&device_id)?;
+        let (on, at_ms) = guard.pending(Local::now().timestamp_millis())?;
+        Some(PendingSwitch { on, at: at_ms / 1000 })
+    }
+
     async fn update_web_state(&self) {
         let mut state = self.web_state.write().await;
+        let now = Local::now();
+        let mut changed = Vec::new();
+        for (room, room_state) in state.rooms.iter_mut() {
+            let Some(room_id) = room_index(room) else {
+                continue;
+            };
+            let before = room_state.summary();
+            room_state.sensor_available = self.sensor_available(room_id);
+            room_state.current_temp = self.last_temp_deci.get(&room_id).copied().unwrap_or(0.0);
+            room_state.target_temp = self.room_target(room_id, now);
+            room_state.relay_available = self.relay_available(room_id);
+            room_state.relay_state = self.last_relay_on_status.get(&room_id)
+                .copied()
+                .unwrap_or(false);
+            room_state.failsafe = self.in_failsafe.contains(&room_id);
+            room_state.window_open_until = self.window_detectors.get(&room_id)
+                .and_then(|detector| detector.paused_until(now.timestamp()));
+            room_state.sensors = self.sensor_states(room_id);
+            room_state.rejected_readings = room_state.sensors.iter().map(|sensor| sensor.rejected_readings).sum();
+            room_state.disabled_until = self.disabled_until.get(&room_id).copied();
+            room_state.mode = self.room_modes.get(&room_id).copied().filter(|mode| !mode.expired(now.timestamp()));
+            room_state.pending_switch = self.pending_switch(room_id);
+            room_state.faults = self.fault_detectors.get(&room_id)
+                .map(|detector| detector.faults().to_vec())
+                .unwrap_or_default();
+            if room_state.summary() != before {
+                changed.push(StatusEvent::Room { room: room.clone(), state: room_state.summary() });
+            }
+        }
+
+        let heat_demand_before = state.heat_demand.clone();
+        state.heat_demand = self.config.heat_demand.enabled.then(|| self.heat_demand_state());
 
-        // Update bedroom state
-        state.bedroom.sensor_available = self.last_message_timestamp.get(BEDROOM_SENSOR_EXPECTED_IP)
-            .map_or(false, |&ts| Local::now().timestamp() - ts < 180);
-        state.bedroom.current_temp = self.last_temp_deci.get(&0).copied().unwrap_or(0.0);
-        state.bedroom.target_temp = interpolate_fn_rust(INTERPOLATE_INTERVALS[0], Local::now());
-        state.bedroom.relay_available = self.last_message_timestamp.get(BEDROOM_RELAY_EXPECTED_IP)
-            .map_or(false, |&ts| Local::now().timestamp() - ts < 180);
-        state.bedroom.relay_state = self.last_relay_on_status.get(BEDROOM_RELAY_EXPECTED_IP)
-            .copied()
-            .unwrap_or(false);
-
-        // Update kids bedroom state
-        state.kids_bedroom.sensor_available = self.last_message_timestamp.get(KIDS_SENSOR_EXPECTED_IP)
-            .map_or(false, |&ts| Local::now().timestamp() - ts < 180);
-        state.kids_bedroom.current_temp = self.last_temp_deci.get(&2).copied().unwrap_or(0.0);
-        state.kids_bedroom.target_temp = interpolate_fn_rust(INTERPOLATE_INTERVALS[2], Local::now());
-        state.kids_bedroom.relay_available = self.last_message_timestamp.get(KIDS_RELAY_EXPECTED_IP)
-            .map_or(false, |&ts| Local::now().timestamp() - ts < 180);
-        state.kids_bedroom.relay_state = self.last_relay_on_status.get(KIDS_RELAY_EXPECTED_IP)
-            .copied()
-            .unwrap_or(false);
+        // Push only actual changes to live subscribers
+        for event in changed {
+            publish(&self.events, event);
+        }
+        if let Some(heat_demand) = state.heat_demand.clone().filter(|heat_demand| Some(heat_demand) != heat_demand_before.as_ref()) {
+            publish(&self.events, StatusEvent::HeatDemand { state: heat_demand });
+        }
     }
 
     async fn new_relay_report(&mut self, src: SocketAddr, report: &RelayReport) -> Result<()> {
-        let client_ip_str = src.ip().to_string();
-        self.last_message_timestamp.insert(client_ip_str.clone(), Local::now().timestamp());
-
-        let device_id = report.info.as_ref().and_then(|i| i.id);
-
-        let header_status = self.print_header(&client_ip_str, report.info.as_ref().unwrap_or(&DeviceInfo::default()));
-        if header_status == PrintHeaderStatus::Failure {
+        let Some(relay_id) = self.check_header(src, report.info.as_ref().unwrap_or(&DeviceInfo::default()), DeviceKind::Relay) else {
             return Ok(());
-        }
+        };
+        // Room index, keys the room's relay state below
+        let Some(device_id) = self.devices.room_of(DeviceKind::Relay, relay_id).and_then(room_index) else {
+            debug!(relay_id, source = %src.ip(), "report from a relay without a room");
+            return Ok(());
+        };
+        self.last_relay_report.insert(device_id, Local::now().timestamp());
 
         let relay_is_on = report.relay_status();
-        self.last_relay_on_status.insert(client_ip_str.clone(), relay_is_on);
+        self.last_relay_on_status.insert(device_id, relay_is_on);
 
         // Update confirmation state
-        if let Some(id_val) = device_id {
-            if let Some(relay_hostname) = RELAYS.get(id_val as usize) {
-                let confirmation_entry = self.relay_confirmations
-                    .entry(relay_hostname.to_string())
-                    .or_default();
-                confirmation_entry.unconfirmed = false;
-                confirmation_entry.confirmed_on_state = relay_is_on;
+        if let Some(relay_hostname) = self.devices.room_relay(room_name(device_id)).map(|relay| relay.host.clone()) {
+            let confirmation_entry = self.relay_confirmations
+                .entry(relay_hostname)
+                .or_default();
+            if confirmation_entry.unconfirmed {
+                self.audit.record(AuditKind::RelayConfirmed { device_id: relay_id, on: relay_is_on });
             }
+            confirmation_entry.unconfirmed = false;
+            confirmation_entry.confirmed_on_state = relay_is_on;
+            self.alerts.resolve(AlertKind::RelayUnconfirmed, room_name(device_id),
+                format!("{} relay confirmed {}", room_name(device_id), if relay_is_on { "ON" } else { "OFF" }),
+                Local::now().timestamp());
+
+            let now = Local::now();
+            let commanded_on = self.relay_guards.get_mut(&device_id).map(|guard| guard.state(now.timestamp_millis()));
+            self.fault_detectors.entry(device_id).or_default().relay_report(now.timestamp(), relay_is_on, commanded_on);
+            self.check_faults(device_id, now.timestamp());
         }
 
-        print!("Relay: {}{}",
-            if relay_is_on { "ON" } else { "OFF" },
-            if header_status == PrintHeaderStatus::HasStatusUpdate { "\n" } else { "\r" }
-        );
-        stdout().flush()?;
+        debug!(relay_id, room = room_name(device_id), source = %src.ip(), relay_on = relay_is_on, "relay report");
         self.update_web_state().await;
         Ok(())
     }
 
-    async fn is_heater_disabled(&self, device_id: u32, current_timestamp: i64) -> bool {
-        // Check if heater is disabled
-        let web_state = self.web_state.read().await;
-        let room_state = if device_id == 0 {
-            &web_state.bedroom
-        } else if device_id == 2 {
-            &web_state.kids_bedroom
+    fn is_heater_disabled(&self, device_id: u32, current_timestamp: i64) -> bool {
+        self.disabled_until.get(&device_id).is_some_and(|until| current_timestamp < *until)
+    }
+
+    // Sends a relay command and tracks its confirmation. Returns whether the relay
+    // never reported back after the previous command, and its last reported state.
+    async fn command_relay(&mut self, device_id: u32, on: bool, delay_ms: u32) -> Result<(bool, bool)> {
+        self.heat_calls.insert(device_id, on ^ (delay_ms != 0));
+        self.update_heat_demand().await;
+
+        let Some(relay_hostname) = self.devices.room_relay(room_name(device_id)).map(|relay| relay.host.clone()) else {
+            warn!(device_id, room = room_name(device_id), "no relay configured for the room");
+            anyhow::bail!("No relay configured for {}", room_name(device_id));
+        };
+        let relay_result = self.sender.set_relay(&relay_hostname, on, delay_ms).await;
+        self.audit.record(AuditKind::RelayCommand {
+            relay: relay_hostname.to_string(),
+            on,
+            delay_ms,
+            error: relay_result.as_ref().err().map(|e| e.to_string()),
+        });
+
+        let confirmation_state = self.relay_confirmations
+            .entry(relay_hostname.to_string())
+            .or_default();
+        let unconfirmed = confirmation_state.unconfirmed;
+        let relay_on = confirmation_state.confirmed_on_state;
+        match relay_result {
+            Ok(()) => {
+                if unconfirmed {
+                    self.audit.record(AuditKind::RelayUnconfirmed { relay: relay_hostname.to_string() });
+                    self.alerts.raise(AlertKind::RelayUnconfirmed, room_name(device_id),
+                        format!("{} did not report back after the previous command", relay_hostname),
+                        Local::now().timestamp());
+                }
+                // Mark as unconfirmed after sending command
+                confirmation_state.unconfirmed = true;
+                Ok((unconfirmed, relay_on))
+            }
+            Err(e) => {
+                warn!(device_id, relay = %relay_hostname, error = %e, "failed to send relay command");
+                Err(e)
+            }
+        }
+    }
+
+    // Frost protection and maximum temperature, the only place that overrides a decision
+    // made from a reading. Returns the relay command to send and the active intervention.
+    // A new intervention is audited, an ongoing one again when it overrides a manual command.
+    fn apply_safety(&mut self, device_id: u32, temp: f64, on: bool, delay_ms: u32, manual: bool) -> (bool, u32, Option<SafetyIntervention>) {
+        let room = room_name(device_id);
+        let (min_temp, max_temp) = self.config.safety.limits(room);
+        let previous = self.safety_active.get(&device_id).copied();
+        let intervention = safety::check((min_temp, max_temp), temp, previous);
+
+        let ongoing = intervention.filter(|_| manual && intervention == previous);
+        if let Some(ongoing) = ongoing.filter(|ongoing| (*ongoing == SafetyIntervention::FrostProtection) != on) {
+            warn!(device_id, room, temp = round2(temp), intervention = ?ongoing, requested_on = on, "safety limit overrides manual relay command");
+            self.audit.record(AuditKind::SafetyOverride {
+                device_id,
+                room: room.to_string(),
+                temperature: temp,
+                intervention: ongoing,
+                requested_on: on,
+            });
+        } else if intervention != previous {
+            match intervention {
+                Some(intervention) => {
+                    warn!(device_id, room, temp = round2(temp), min_temp, max_temp, ?intervention, requested_on = on, "safety intervention");
+                    self.audit.record(AuditKind::SafetyOverride {
+                        device_id,
+                        room: room.to_string(),
+                        temperature: temp,
+                        intervention,
+                        requested_on: on,
+                    });
+                    self.alerts.raise(AlertKind::TemperatureOutOfBounds, room,
+                        format!("{} is at {:.1}°C, limits {:.1}..{:.1}°C, heater forced {}", room, temp, min_temp, max_temp,
+                            if intervention == SafetyIntervention::FrostProtection { "ON" } else { "OFF" }),
+                        Local::now().timestamp());
+                }
+                None => {
+                    info!(device_id, room, temp = round2(temp), "safety limit cleared");
+                    self.alerts.resolve(AlertKind::TemperatureOutOfBounds, room,
+                        format!("{} is back within limits at {:.1}°C", room, temp), Local::now().timestamp());
+                }
+            }
+        }
+
+        match intervention {
+            Some(intervention) => self.safety_active.insert(device_id, intervention),
+            None => self.safety_active.remove(&device_id),
+        };
+        match intervention {
+            Some(SafetyIntervention::FrostProtection) => (true, 0, intervention),
+            Some(SafetyIntervention::OverTemperature) => (false, 0, intervention),
+            None => (on, delay_ms, None),
+        }
+    }
+
+    // Minimum on/off times and switch rate, between the strategy and the relay.
+    // Forced commands are sent unchanged but still count as switches. With `only_changes`
+    // None is returned and nothing recorded when the relay already does this.
+    fn protect_relay(&mut self, device_id: u32, on: bool, delay_ms: u32, forced: bool, now: DateTime<Local>,
+        only_changes: bool) -> Option<(bool, u32, Option<ProtectionReason>)> {
+        let room = room_name(device_id);
+        let limits = self.config.relay_protection.limits(room);
+        let guard = self.relay_guards.entry(device_id).or_default();
+        let before = only_changes.then(|| guard.clone());
+        let now_ms = now.timestamp_millis();
+        let requested = RelayCommand { on, delay_ms };
+        let (command, reason) = if forced {
+            guard.record(requested, now_ms);
+            (requested, None)
         } else {
-            &web_state.bedroom // Default to bedroom, won't be used
+            guard.apply(&limits, requested, now_ms)
         };
+        if let Some(mut before) = before {
+            if !before.changes(command, now_ms) {
+                *guard = before;
+                return None;
+            }
+        }
 
-        return room_state.disabled_until
-            .map(|until| current_timestamp < until)
-            .unwrap_or(false);
+        if let Some(reason) = reason {
+            info!(device_id, room, ?reason,
+                requested_on = on, requested_delay_min = round2(delay_ms as f64 / 60_000.0),
+                command_on = command.on, command_delay_min = round2(command.delay_ms as f64 / 60_000.0),
+                "relay protection adjusted command");
+        }
+        Some((command.on, command.delay_ms, reason))
     }
 
-    async fn new_sensor_report(&mut self, src: SocketAddr, report: &SensorReport) -> Result<()> {
-        let client_ip_str = src.ip().to_string();
+    // Everything between a room's request and its relay: forced heat replaces the request,
+    // a pause switches off, safety limits win over both, and relay protection applies unless
+    // one of them forced the command. Shared by readings, control ticks and the failsafe.
+    // With `only_changes` nothing is sent while the relay already does this.
+    async fn drive_relay(&mut self, device_id: u32, temp: Option<f64>, request: RelayRequest, now: DateTime<Local>,
+        only_changes: bool) -> Option<RelayOutcome> {
+        let (on, delay_ms) = if request.force_heat { (true, 0) } else { (request.on, request.delay_ms) };
+        let (on, delay_ms, safety) = match temp {
+            Some(temp) => self.apply_safety(device_id, temp, on & !request.paused, delay_ms, request.manual),
+            None => (on & !request.paused, delay_ms, None),
+        };
+        let forced = safety.is_some() || request.paused || request.force_heat || request.manual;
+        let (on, delay_ms, protection) = self.protect_relay(device_id, on, delay_ms, forced, now, only_changes)?;
+        // Failures are already logged and audited
+        let result = self.command_relay(device_id, on, delay_ms).await;
+        let sent = result.is_ok();
+        let (unconfirmed, relay_on) = result.unwrap_or_default();
+        Some(RelayOutcome { on, delay_ms, safety, protection, unconfirmed, relay_on, sent })
+    }
+
+    fn record_decision(&self, device_id: u32, temp: f64, target: f64, decision: &Decision, disabled: bool) {
+        self.audit.record(AuditKind::ControlDecision {
+            device_id,
+            temperature: temp,
+            target,
+            heater_on: decision.on,
+            delay_ms: decision.delay_ms,
+            disabled,
+            diagnostics: decision.diagnostics.clone(),
+        });
+    }
+
+    // Pauses heating after a sharp temperature drop, returns whether the room is paused
+    fn check_open_window(&mut self, device_id: u32, timestamp: i64, temp: f64, humidity: f64) -> bool {
+        let detector = self.window_detectors.entry(device_id).or_default();
+        if let Some(opened) = detector.update(&self.config.open_window, timestamp, temp, humidity) {
+            let room = room_name(device_id);
+            warn!(device_id, room, temp_drop = round2(opened.temp_drop), humidity_drop = round2(opened.humidity_drop),
+                pause_min = self.config.open_window.pause_min, "open window detected, heating paused");
+            self.audit.record(AuditKind::WindowOpen {
+                device_id,
+                room: room.to_string(),
+                temp_drop: opened.temp_drop,
+                humidity_drop: opened.humidity_drop,
+                paused_until: opened.paused_until,
+            });
+        }
+        detector.is_paused(timestamp)
+    }
+
+    // Heater not warming the room, or relay not following commands
+    fn check_faults(&mut self, device_id: u32, timestamp: i64) {
+        let room = room_name(device_id);
+        let changes = self.fault_detectors.entry(device_id).or_default().check(&self.config.fault, timestamp);
+        for fault in changes.raised {
+            error!(device_id, room, ?fault, "heater fault");
+            self.alerts.raise(AlertKind::HeaterFault, room, format!("{}: {:?}", room, fault), timestamp);
+            self.audit.record(AuditKind::FaultRaised { device_id, room: room.to_string(), fault });
+        }
+        for fault in changes.cleared {
+            info!(device_id, room, fault, "heater fault cleared");
+            self.audit.record(AuditKind::FaultCleared { device_id, room: room.to_string(), fault: fault.to_string() });
+        }
+        if self.fault_detectors.get(&device_id).is_some_and(|detector| detector.faults().is_empty()) {
+            self.alerts.resolve(AlertKind::HeaterFault, room, format!("{}: heater works again", room), timestamp);
+        }
+    }
+
+    // Plausibility checks on the raw reading, rejected ones are logged and counted.
+    // A sensor sending only rejected readings ends up in failsafe.
+    fn accept_reading(&mut self, device_id: u32, report: &SensorReport) -> bool {
+        if !self.config.sensor_filter.enabled {
+            return true;
+        }
+        let temp = report.temperature_deci() as f64 * 0.1;
+        let humidity = report.humidity_deci() as f64 * 0.1;
+        let timestamp = Local::now().timestamp();
+        let sensor = self.devices.sensor_name(device_id);
+        let limits = self.config.sensor_filter.limits(&sensor);
+        let filter = self.sensor_filters.entry(device_id).or_default();
+        let Some(reason) = filter.check(&limits, timestamp, temp, humidity) else {
+            return true;
+        };
+        warn!(device_id, sensor, temp = round2(temp), humidity = round2(humidity),
+            reason = reason.name(), rejected = filter.rejected(), "sensor reading rejected");
+        self.count_sensor_error(device_id, reason.name(), timestamp);
+        false
+    }
+
+    fn count_sensor_error(&mut self, device_id: u32, error_name: &str, timestamp: i64) {
+        let errors = self.sensor_errors.entry(device_id).or_default();
+        errors.push_back(timestamp);
+        let window_start = timestamp - self.config.alert.error_burst_min as i64 * 60;
+        while errors.front().is_some_and(|&at| at < window_start) {
+            errors.pop_front();
+        }
+        if errors.len() >= self.config.alert.error_burst_count {
+            let sensor = self.devices.sensor_name(device_id);
+            self.alerts.raise(AlertKind::SensorErrors, &sensor,
+                format!("{} sensor reported {} errors within {} minutes, last {}", sensor, errors.len(),
+                    self.config.alert.error_burst_min, error_name),
+                timestamp);
+        }
+    }
+
+    // Called periodically: offline devices and sensor error bursts that are over
+    fn check_alerts(&mut self) {
+        let now = Local::now().timestamp();
+        let offline_sec = self.config.alert.offline_sec;
+        for device_id in self.room_sensors() {
+            let sensor = self.devices.sensor_name(device_id);
+            let sensor_silent = now - self.sensor_readings.get(&device_id).map_or(self.started_at, |reading| reading.timestamp);
+            if sensor_silent >= offline_sec {
+                self.alerts.raise(AlertKind::SensorOffline, &sensor,
+                    format!("No readings from the {} sensor for {} minutes", sensor, sensor_silent / 60), now);
+            } else {
+                self.alerts.resolve(AlertKind::SensorOffline, &sensor, format!("{} sensor reports again", sensor), now);
+            }
+
+            let window_start = now - self.config.alert.error_burst_min as i64 * 60;
+            let errors = self.sensor_errors.get(&device_id)
+                .map_or(0, |errors| errors.iter().filter(|&&at| at >= window_start).count());
+            if errors < self.config.alert.error_burst_count {
+                self.alerts.resolve(AlertKind::SensorErrors, &sensor, format!("{} sensor errors stopped", sensor), now);
+            }
+        }
 
-        let header_status = self.print_header(&client_ip_str, report.info.as_ref().unwrap_or(&DeviceInfo::default()));
-        if header_status == PrintHeaderStatus::Failure {
-            // Still update last_message_timestamp even if header fails but message has ID
-            if report.info.as_ref().and_then(|i| i.id).is_some() {
-                 self.last_message_timestamp.insert(client_ip_str.clone(), Local::now().timestamp());
+        for device_id in 0..ROOM_NAMES.len() as u32 {
+            let room = room_name(device_id);
+            let Some(relay_hostname) = self.devices.room_relay(room).map(|relay| relay.host.clone()) else {
+                continue;
+            };
+            let relay_silent = now - self.last_relay_report.get(&device_id).copied().unwrap_or(self.started_at);
+            if relay_silent >= offline_sec {
+                self.alerts.raise(AlertKind::RelayOffline, room,
+                    format!("No reports from {} for {} minutes", relay_hostname, relay_silent / 60), now);
+            } else {
+                self.alerts.resolve(AlertKind::RelayOffline, room, format!("{} reports again", relay_hostname), now);
             }
+        }
+    }
+
+    // Device ids of all sensors used by a room
+    fn room_sensors(&self) -> Vec<u32> {
+        let mut sensors: Vec<u32> = (0..INTERPOLATE_INTERVALS.len() as u32)
+            .flat_map(|room_id| self.room_fusion(room_id).sensors)
+            .collect();
+        sensors.sort();
+        sensors.dedup();
+        sensors
+    }
+
+    fn sensor_silent_sec(&self, device_id: u32, now_ts: i64) -> i64 {
+        now_ts - self.last_sensor_report.get(&device_id).copied().unwrap_or(self.started_at)
+    }
+
+    // Periodic work of the control task
+    async fn check(&mut self) {
+        // Run-on and minimum times end without a relay command
+        self.update_heat_demand().await;
+        self.check_silent_sensors().await;
+        self.check_alerts();
+    }
+
+    // Re-evaluates every room between readings: schedule transitions, ended modes and
+    // disables, and delayed switches the relay did by itself
+    async fn control_tick(&mut self) {
+        let now = Local::now();
+        for device_id in 0..ROOM_NAMES.len() as u32 {
+            self.tick_room(device_id, now).await;
+        }
+        self.update_web_state().await;
+    }
+
+    // Same decision path as control_room with the last temperature, the relay is only
+    // commanded when its state or pending switch changes
+    async fn tick_room(&mut self, device_id: u32, now: DateTime<Local>) {
+        let timestamp = now.timestamp();
+        // Rooms without fresh readings are driven by the failsafe
+        if self.in_failsafe.contains(&device_id) || self.sensor_silent_sec(device_id, timestamp) >= self.config.failsafe.timeout_sec {
+            return;
+        }
+        let room = room_name(device_id);
+        let Some(temp) = self.last_temp_deci.get(&device_id).copied() else {
+            return;
+        };
+        if self.devices.room_relay(room).is_none() {
+            return;
+        }
+
+        let mode = self.room_mode(device_id, timestamp);
+        let force_heat = matches!(mode, Some(RoomMode::ForceHeat { .. }));
+        let target_temp = self.room_target(device_id, now);
+        let future_target_temp = self.room_target(device_id, now + chrono::Duration::minutes(10));
+        let is_disabled = self.is_heater_disabled(device_id, timestamp);
+        let window_open = self.window_detectors.get(&device_id).is_some_and(|detector| detector.is_paused(timestamp));
+        let Some(decision) = self.controls.get_mut(device_id as usize)
+            .and_then(|control| control.reevaluate(target_temp, future_target_temp, now)) else {
+            return;
+        };
+
+        let request = RelayRequest { on: decision.on, delay_ms: decision.delay_ms, force_heat, paused: is_disabled || window_open, manual: false };
+        let Some(outcome) = self.drive_relay(device_id, Some(temp), request, now, true).await else {
+            return;
+        };
+        if let Some(control) = self.controls.get_mut(device_id as usize) {
+            control.set_output(decision.on, decision.delay_ms, now);
+        }
+        self.record_decision(device_id, temp, target_temp, &decision, is_disabled);
+
+        let diagnostics = &decision.diagnostics;
+        info!(
+            device_id,
+            room,
+            temp = round2(temp),
+            target = round2(target_temp),
+            decision = if decision.on { "ON" } else { "OFF" },
+            delay_min = round2(decision.delay_ms as f64 / 60_000.0),
+            command_on = outcome.on,
+            command_delay_min = round2(outcome.delay_ms as f64 / 60_000.0),
+            relay_on = outcome.relay_on,
+            disabled = is_disabled,
+            window_open,
+            mode = ?mode,
+            unconfirmed = outcome.unconfirmed,
+            reason = ?diagnostics.reason,
+            dt = diagnostics.dt.map(round2),
+            offset = diagnostics.offset.map(round2),
+            pulse_width = diagnostics.pulse_width.map(round2),
+            minutes_in_state = diagnostics.minutes_in_state.map(round2),
+            safety = ?outcome.safety,
+            protection = ?outcome.protection,
+            "control tick"
+        );
+    }
+
+    async fn web_command(&mut self, command: WebCommand) {
+        match command {
+            WebCommand::SetRelay { room, on, reply } => {
+                let result = self.manual_relay(&room, on).await;
+                let _ = reply.send(result.map_err(|e| e.to_string()));
+            }
+            WebCommand::Disable { room, disable, reply } => {
+                let result = self.disable_room(&room, disable).await;
+                let _ = reply.send(result.map_err(|e| e.to_string()));
+            }
+        }
+        self.update_web_state().await;
+    }
+
+    // Relay switched from the web UI, the strategy takes over again with the next reading
+    // or control tick, whichever comes first
+    async fn manual_relay(&mut self, room: &str, on: bool) -> Result<()> {
+        let room_id = room_index(room).context("Invalid room")?;
+        let now = Local::now();
+        // Safety limits win over the web UI too, checked with the room's last temperature
+        let temp = self.last_temp_deci.get(&room_id).copied().filter(|_| !self.in_failsafe.contains(&room_id));
+        let request = RelayRequest { on, delay_ms: 0, force_heat: false, paused: false, manual: true };
+        let Some(outcome) = self.drive_relay(room_id, temp, request, now, false).await else {
+            unreachable!("commands are always sent without only_changes");
+        };
+        if let Some(control) = self.controls.get_mut(room_id as usize) {
+            control.set_output(outcome.on, 0, now);
+        }
+        if !outcome.sent {
+            anyhow::bail!("Failed to send the relay command for {}", room);
+        }
+        // Shown until the relay reports its actual state
+        self.last_relay_on_status.insert(room_id, outcome.on);
+        Ok(())
+    }
+
+    // Heating off for two hours, a running heater is switched off right away
+    async fn disable_room(&mut self, room: &str, disable: bool) -> Result<()> {
+        let room_id = room_index(room).context("Invalid room")?;
+        if !disable {
+            self.disabled_until.remove(&room_id);
             return Ok(());
         }
+        self.disabled_until.insert(room_id, Local::now().timestamp() + 2 * 3600);
+        if self.last_relay_on_status.get(&room_id).copied().unwrap_or(false) && self.devices.room_relay(room).is_some() {
+            self.manual_relay(room, false).await?;
+        }
+        Ok(())
+    }
 
-        let device_id = report.info.as_ref().and_then(|i| i.id).unwrap_or(u32::MAX); // Use a sentinel if no ID
+    // Called periodically: rooms whose sensor went silent get their relay driven by
+    // the configured failsafe until the sensor reports again
+    async fn check_silent_sensors(&mut self) {
+        let now = Local::now();
+        for device_id in 0..ROOM_NAMES.len() as u32 {
+            let silent_sec = self.sensor_silent_sec(device_id, now.timestamp());
+            if silent_sec < self.config.failsafe.timeout_sec {
+                continue;
+            }
+            let room = room_name(device_id);
+            let mode = self.config.failsafe.mode(room);
+            let started = self.in_failsafe.insert(device_id);
+            if started {
+                warn!(device_id, room, silent_sec, failsafe = mode.name(), "sensor silent, relay switched to failsafe");
+                self.audit.record(AuditKind::FailsafeStarted {
+                    device_id,
+                    room: room.to_string(),
+                    silent_sec,
+                    mode: mode.name().to_string(),
+                });
+            }
+            if self.devices.room_relay(room).is_none() {
+                continue;
+            }
 
-        if report.has_sensor_error() {
+            let is_disabled = self.is_heater_disabled(device_id, now.timestamp());
+            let force_heat = matches!(self.room_mode(device_id, now.timestamp()), Some(RoomMode::ForceHeat { .. }));
+            let (on, delay_ms, temp) = self.failsafe_output(device_id, &mode, now);
+            let request = RelayRequest { on, delay_ms, force_heat, paused: is_disabled || mode == FailsafeMode::Off, manual: false };
+            // The relay state is unknown when the failsafe starts, later only changes are sent
+            if let Some(outcome) = self.drive_relay(device_id, temp, request, now, !started).await {
+                debug!(device_id, room, failsafe = mode.name(), on = outcome.on, delay_ms = outcome.delay_ms,
+                    safety = ?outcome.safety, protection = ?outcome.protection, "failsafe relay command");
+            }
+        }
+        self.update_web_state().await;
+    }
+
+    // Requested relay state and, for follow_room, the temperature it was controlled with
+    fn failsafe_output(&mut self, device_id: u32, mode: &FailsafeMode, now: DateTime<Local>) -> (bool, u32, Option<f64>) {
+        match mode {
+            FailsafeMode::Off => (false, 0, None),
+            FailsafeMode::DutyCycle { on_minutes, period_minutes } => {
+                (duty_cycle_on(*on_minutes, *period_minutes, now.timestamp()), 0, None)
+            }
+            FailsafeMode::FollowRoom { room } => {
+                let Some(other_id) = room_index(room) else {
+                    return (false, 0, None);
+                };
+                let other_temp = self.last_temp_deci.get(&other_id).copied();
+                let Some(temp) = other_temp.filter(|_| self.sensor_silent_sec(other_id, now.timestamp()) < self.config.failsafe.timeout_sec) else {
+                    return (false, 0, None);
+                };
+                // Boost and away still apply to the room's own target
+                let target_temp = self.room_target(device_id, now);
+                let future_target_temp = self.room_target(device_id, now + chrono::Duration::minutes(10));
+                let control = &mut self.controls[device_id as usize];
+                let decision = control.get_mode(temp, target_temp, future_target_temp, now);
+                control.set_output(decision.on, decision.delay_ms, now);
+                (decision.on, decision.delay_ms, Some(temp))
+            }
+        }
+    }
+
+    async fn new_sensor_report(&mut self, src: SocketAddr, report: &SensorReport) -> Result<()> {
+        let Some(device_id) = self.check_header(src, report.info.as_ref().unwrap_or(&DeviceInfo::default()), DeviceKind::Sensor) else {
+            return Ok(());
+        };
+
+        let pressed_room = self.check_button(src, device_id, report);
+
+        if report.has_sensor_error() && report.sensor_error() != SensorError::S_BUTTON_EVENT {
             let error_name = match SensorError::try_from(report.sensor_error()).unwrap_or(SensorError::S_CHECKSUM) {
                  SensorError::S_TIMEOUT_LOW_PULSE => "S_TIMEOUT_LOW_PULSE",
                  SensorError::S_TIMEOUT_HIGH_PULSE => "S_TIMEOUT_HIGH_PULSE",
@@ -314,192 +1072,544 @@ impl Server {
                  SensorError::S_CHECKSUM => "S_CHECKSUM",
                  SensorError::S_BUTTON_EVENT => "S_BUTTON_EVENT",
             };
-            print!("({}) ", error_name);
-        } else if report.has_temperature_deci() {
-            let temp = report.temperature_deci() as f64 * 0.1;
-            let humidity = report.humidity_deci() as f64 * 0.1;
-            print!("t={:.1} h={:.1} ", temp, humidity);
+            warn!(device_id, sensor = self.devices.sensor_name(device_id), source = %src.ip(), error = error_name, "sensor error");
+            self.inventory.sensor_error(device_id, error_name);
+            self.count_sensor_error(device_id, error_name, Local::now().timestamp());
         }
 
-        if !report.has_temperature_deci() {
-            println!(); // End line if no temperature data
-            return Ok(());
+        let controlled = self.sensor_reading(src, device_id, report).await?;
+        // A new mode applies right away, not with the room's next reading
+        if let Some(room_id) = pressed_room.filter(|room_id| !controlled.contains(room_id)) {
+            let now = Local::now().timestamp();
+            let stale_sec = self.config.sensor_fusion.stale_sec;
+            if let Some(fused) = self.room_fusion(room_id).fuse(&self.sensor_readings, now, stale_sec) {
+                let temp = fused.temperature;
+                self.control_room(room_id, src, temp, fused).await?;
+            }
         }
 
-        self.last_message_timestamp.insert(client_ip_str.clone(), Local::now().timestamp());
+        self.update_web_state().await;
+        Ok(())
+    }
 
-        let mut temp = report.temperature_deci() as f64 * 0.1;
-        let humidity = report.humidity_deci() as f64 * 0.1; // For Netdata
+    // Button presses come as S_BUTTON_EVENT or as the button state changing to
+    // B_FORCE_ON. Returns the room whose mode changed.
+    fn check_button(&mut self, src: SocketAddr, device_id: u32, report: &SensorReport) -> Option<u32> {
+        let mut pressed = report.has_sensor_error() && report.sensor_error() == SensorError::S_BUTTON_EVENT;
+        if report.has_button() {
+            let force_on = report.button() == ButtonState::B_FORCE_ON;
+            pressed |= force_on && !self.button_states.insert(device_id, force_on).unwrap_or(false);
+        }
+        if !pressed {
+            return None;
+        }
+        let Some(room_id) = self.devices.room_of(DeviceKind::Sensor, device_id).and_then(room_index) else {
+            info!(device_id, source = %src.ip(), "button pressed on a sensor without a room");
+            return None;
+        };
+        let room = room_name(room_id);
+        let now = Local::now().timestamp();
+        let mode = self.config.button.press(room, self.room_modes.get(&room_id).copied(), now);
+        match mode {
+            Some(mode) => self.room_modes.insert(room_id, mode),
+            None => self.room_modes.remove(&room_id),
+        };
+        info!(device_id, room, source = %src.ip(), mode = ?mode, "button pressed");
+        self.audit.record(AuditKind::ButtonPressed { device_id, room: room.to_string(), mode });
+        Some(room_id)
+    }
 
-        let current_time = Local::now();
-        let current_timestamp = current_time.timestamp();
+    // Active button mode of the room, expired modes end here
+    fn room_mode(&mut self, device_id: u32, now: i64) -> Option<RoomMode> {
+        let mode = *self.room_modes.get(&device_id)?;
+        if !mode.expired(now) {
+            return Some(mode);
+        }
+        self.room_modes.remove(&device_id);
+        info!(device_id, room = room_name(device_id), mode = ?mode, "button mode ended");
+        self.audit.record(AuditKind::ButtonModeEnded { device_id, room: room_name(device_id).to_string(), mode });
+        None
+    }
 
-        let mut target_temp = temp; // Default target to current temp if not controlled
-        let mut heater_on = false;
+    // Rooms calling for heat, and sources of heat_on messages that are still fresh
+    fn calling_rooms(&self, now: i64) -> Vec<String> {
+        let timeout_sec = self.config.heat_demand.external_timeout_sec;
+        let mut rooms: Vec<String> = self.heat_calls.iter()
+            .filter(|(_, on)| **on)
+            .map(|(device_id, _)| room_name(*device_id).to_string())
+            .chain(self.external_demand.iter()
+                .filter(|(_, (on, timestamp))| *on && now - timestamp < timeout_sec)
+                .map(|(source, _)| source.to_string()))
+            .collect();
+        rooms.sort();
+        rooms
+    }
 
-        if (device_id as usize) < INTERPOLATE_INTERVALS.len() { // Check if ID is within manageable range
-            target_temp = interpolate_fn_rust(INTERPOLATE_INTERVALS[device_id as usize], current_time);
+    // Drives the boiler relay and heat_on devices from the aggregated demand
+    async fn update_heat_demand(&mut self) {
+        if !self.config.heat_demand.enabled {
+            return;
+        }
+        let now = Local::now().timestamp();
+        let rooms = self.calling_rooms(now);
+        let calling: Vec<&str> = rooms.iter().map(String::as_str).collect();
+        let demand = self.config.heat_demand.demand(&calling);
+        let was_on = self.heat_demand.on();
+        let Some(on) = self.heat_demand.update(&self.config.heat_demand, demand, now) else {
+            return;
+        };
+        if on != was_on {
+            info!(on, demand, rooms = ?rooms, "heat demand switched");
+            self.audit.record(AuditKind::HeatDemand { on, rooms });
+        }
+        self.send_heat_demand(on).await;
+    }
 
-            temp += CORRECTION[device_id as usize];
-            print!("{:.1} (target {:.1}) ", temp, target_temp);
-            self.last_temp_deci.insert(device_id, temp);
+    // Boiler relay and heat_on subscribers
+    async fn send_heat_demand(&self, on: bool) {
+        if let Some(relay) = &self.config.heat_demand.relay {
+            let result = self.sender.set_relay(relay, on, 0).await;
+            self.audit.record(AuditKind::RelayCommand {
+                relay: relay.clone(),
+                on,
+                delay_ms: 0,
+                error: result.as_ref().err().map(|e| e.to_string()),
+            });
+            if let Err(e) = result {
+                warn!(relay = %relay, error = %e, "failed to send boiler relay command");
+            }
+        }
+        for target in &self.config.heat_demand.publish {
+            if let Err(e) = self.sender.send_heat_on(target, on).await {
+                warn!(target = %target, error = %e, "failed to publish heat_on");
+            }
+        }
+    }
 
-            let is_disabled = self.is_heater_disabled(device_id, current_timestamp).await;
-            let future_target_temp = interpolate_fn_rust(INTERPOLATE_INTERVALS[device_id as usize], current_time + chrono::Duration::minutes(10));
+    // Leaves relays in the configured safe state and saves the state for the next start
+    async fn shutdown(&mut self, received: &str) {
+        info!(signal = received, "shutting down");
+        self.audit.record(AuditKind::Shutdown { signal: received.to_string() });
+        for room_id in 0..ROOM_NAMES.len() as u32 {
+            let room = room_name(room_id);
+            let Some(on) = self.config.shutdown.relay_state(room).output() else {
+                continue;
+            };
+            if self.devices.room_relay(room).is_some() {
+                info!(room, on, "setting relay to its shutdown state");
+                // Without a delay, replaces a pending delayed command
+                let _ = self.command_relay(room_id, on, 0).await;
+            }
+        }
+        if self.config.heat_demand.enabled {
+            if let Some(on) = self.config.shutdown.boiler_state.output() {
+                self.send_heat_demand(on).await;
+            }
+        }
+        self.save_state().await;
+    }
 
-            if let Some(control_strategy) = self.controls.get_mut(device_id as usize) {
-                let (mode_on, delay_ms) = control_strategy.get_mode(
-                    temp,
-                    target_temp,
-                    future_target_temp,
-                    current_time
-                );
-                // Call set_output on the control strategy object itself (for its internal state)
-                control_strategy.set_output(mode_on, delay_ms, current_time);
+    async fn save_state(&self) {
+        let path = &self.config.shutdown.state_file;
+        if path.as_os_str().is_empty() {
+            return;
+        }
+        let web_state = self.web_state.read().await;
+        let mut state = SavedState { saved_at: Local::now().timestamp(), ..Default::default() };
+        for (room_id, room) in ROOM_NAMES.iter().enumerate() {
+            let room_state = web_state.room(room);
+            state.rooms.insert(room.to_string(), SavedRoom {
+                temperature_history: room_state.map(|room_state| room_state.temperature_history.clone()).unwrap_or_default(),
+                disabled_until: self.disabled_until.get(&(room_id as u32)).copied(),
+                mode: self.room_modes.get(&(room_id as u32)).copied(),
+                offset: self.controls[room_id].learned_offset(),
+            });
+        }
+        match state.save(path) {
+            Ok(()) => info!(path = %path.display(), "state saved"),
+            Err(e) => error!("Failed to save state: {:#}", e),
+        }
+    }
 
-                // If delay is not zero, than mode_on is still opposite for now
-                heater_on = mode_on ^ (delay_ms != 0);
+    // History, disabled rooms, button modes and learned offsets saved by the last shutdown
+    async fn restore_state(&mut self) {
+        let path = self.config.shutdown.state_file.clone();
+        if path.as_os_str().is_empty() {
+            return;
+        }
+        let state = match SavedState::load(&path) {
+            Ok(Some(state)) => state,
+            Ok(None) => return,
+            Err(e) => {
+                warn!("Ignoring saved state: {:#}", e);
+                return;
+            }
+        };
+        let now = Local::now().timestamp();
+        let web_state = self.web_state.clone();
+        let mut web_state = web_state.write().await;
+        for (room_id, room) in ROOM_NAMES.iter().enumerate() {
+            let Some(saved) = state.rooms.get(*room) else {
+                continue;
+            };
+            if let Some(room_state) = web_state.room_mut(room) {
+                let cutout = now - 3600 * 48;
+                room_state.temperature_history = saved.temperature_history.iter()
+                    .filter(|point| point.timestamp >= cutout)
+                    .cloned()
+                    .collect();
+            }
+            if let Some(until) = saved.disabled_until.filter(|until| *until > now) {
+                self.disabled_until.insert(room_id as u32, until);
+            }
+            if let Some(mode) = saved.mode.filter(|mode| !mode.expired(now)) {
+                self.room_modes.insert(room_id as u32, mode);
+            }
+            if let Some(offset) = saved.offset {
+                self.controls[room_id].restore_offset(offset);
+            }
+        }
+        drop(web_state);
+        info!(path = %path.display(), saved_at = state.saved_at, "state restored");
+        self.update_web_state().await;
+    }
 
-                // Now, command the actual relay and log according to C++ logic
-                let relay_hostname = RELAYS[device_id as usize];
+    // Room temperatures for systemctl status, e.g. "bedroom 20.4/21.0 ON, irina N/A/19.0 -"
+    fn status_line(&self) -> String {
+        let now = Local::now();
+        (0..ROOM_NAMES.len() as u32)
+            .map(|room_id| format!("{} {}/{:.1} {}",
+                room_name(room_id),
+                self.last_temp_deci.get(&room_id).map_or_else(|| "N/A".to_string(), |temp| format!("{:.1}", temp)),
+                self.room_target(room_id, now),
+                self.last_relay_on_status.get(&room_id).map_or("-", |on| if *on { "ON" } else { "OFF" })))
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
 
-                // C++ Relay::set_relay logging part 1: Print ON/OFF if delay is 0
-                if delay_ms == 0 {
-                    print!("{}", if mode_on { "ON" } else { "OFF" });
-                }
+    fn heat_demand_state(&self) -> HeatDemandState {
+        let rooms = self.calling_rooms(Local::now().timestamp());
+        let calling: Vec<&str> = rooms.iter().map(String::as_str).collect();
+        HeatDemandState {
+            weight: self.config.heat_demand.weight(&calling),
+            demand: self.config.heat_demand.demand(&calling),
+            on: self.heat_demand.on(),
+            since: self.heat_demand.changed_at(),
+            rooms,
+        }
+    }
 
-                if is_disabled {
-                    print!(" [DISABLED]");
-                }
+    // Scheduled target, adjusted by the room's button mode
+    fn room_target(&self, device_id: u32, time: DateTime<Local>) -> f64 {
+        let scheduled = interpolate_fn_rust(INTERPOLATE_INTERVALS[device_id as usize], time);
+        self.room_modes.get(&device_id)
+            .filter(|mode| !mode.expired(time.timestamp()))
+            .map_or(scheduled, |mode| mode.target(scheduled))
+    }
 
-                // Send the command
-                match set_relay(relay_hostname, mode_on & !is_disabled, delay_ms) {
-                    Ok(_) => {
-                        let confirmation_state = self.relay_confirmations
-                            .entry(relay_hostname.to_string())
-                            .or_default();
-
-                        // C++ Relay::set_relay logging part 2: Print status based on confirmation
-                        if confirmation_state.unconfirmed {
-                            print!(" [UNCONFIRMED]");
-                        } else if delay_ms != 0 {
-                            // Print current *confirmed* state before new command with delay
-                            print!(" {}", if confirmation_state.confirmed_on_state { "*ON" } else { "*OFF" });
-                        }
-
-                        if delay_ms != 0 {
-                            print!(" ({:.1}m->{})",
-                                delay_ms as f64 / 60_000.0,
-                                if mode_on { "ON" } else { "OFF" });
-                        }
-
-                        // Mark as unconfirmed after sending command
-                        confirmation_state.unconfirmed = true;
-                    }
-                    Err(_e) => {
-                        print!(" [NRELAY]");
-                    }
+    // Stores an accepted reading and controls the rooms it drives, returns those rooms
+    async fn sensor_reading(&mut self, src: SocketAddr, device_id: u32, report: &SensorReport) -> Result<Vec<u32>> {
+        if !report.has_temperature_deci() || !self.accept_reading(device_id, report) {
+            return Ok(Vec::new());
+        }
+        let now = Local::now().timestamp();
+        let raw_temp = report.temperature_deci() as f64 * 0.1;
+        let raw_humidity = report.humidity_deci() as f64 * 0.1;
+        if self.calibrator.is_reference(device_id) {
+            self.calibrator.reference_reading(now, raw_temp, Some(raw_humidity));
+        }
+        let sensor = self.devices.sensor_name(device_id);
+        self.calibrator.sensor_reading(&sensor, now, raw_temp, raw_humidity);
+        let calibration = self.calibrator.calibration(&sensor);
+        let reading = SensorReading {
+            timestamp: now,
+            temperature: calibration.temperature(raw_temp),
+            humidity: calibration.humidity(raw_humidity),
+        };
+        self.sensor_readings.insert(device_id, reading);
+
+        // Rooms driven by this sensor are controlled now, the others only
+        // take the reading into their fusion
+        let mut managed = false;
+        let mut controlled = Vec::new();
+        for room_id in 0..INTERPOLATE_INTERVALS.len() as u32 {
+            let room_sensors = self.room_fusion(room_id);
+            managed |= room_sensors.sensors.contains(&device_id);
+            let stale_sec = self.config.sensor_fusion.stale_sec;
+            if room_sensors.driver(&self.sensor_readings, now, stale_sec) != Some(device_id) {
+                continue;
+            }
+            if let Some(fused) = room_sensors.fuse(&self.sensor_readings, now, stale_sec) {
+                self.last_sensor_report.insert(room_id, now);
+                if self.in_failsafe.remove(&room_id) {
+                    info!(device_id = room_id, room = room_name(room_id), "sensor is back, failsafe ended");
+                    self.audit.record(AuditKind::FailsafeEnded { device_id: room_id, room: room_name(room_id).to_string() });
                 }
-            } else {
-                print!("[NO_CONTROL_FOR_ID:{}] ", device_id);
+                self.control_room(room_id, src, raw_temp, fused).await?;
+                controlled.push(room_id);
             }
-            // Update web state after processing the report
-            self.update_history(device_id, current_timestamp, temp, target_temp, heater_on, is_disabled).await?;
-        } else {
-            // Device ID out of range for configured controls/relays
-            print!("{:.1} (unmanaged) ", temp);
-            self.last_temp_deci.insert(device_id, temp); // Still store its temp if needed elsewhere
         }
+        if !managed {
+            // Kept in sensor_readings only, room state and Netdata files are keyed by room index
+            info!(device_id, sensor = self.devices.sensor_name(device_id), source = %src.ip(),
+                temp = round2(reading.temperature), humidity = round2(reading.humidity), "unmanaged sensor report");
+        }
+        Ok(controlled)
+    }
 
+    // Runs the room's strategy with the fused temperature of its sensors.
+    // `device_id` is the room index, which is also its relay's id.
+    async fn control_room(&mut self, device_id: u32, src: SocketAddr, raw_temp: f64, fused: Fused) -> Result<()> {
+        let temp = fused.temperature;
+        let humidity = fused.humidity; // For Netdata and open window detection
+        if self.fused_sensors.insert(device_id, fused.sensors.clone()).is_some_and(|previous| previous != fused.sensors) {
+            // A sensor joined or dropped out, the step is no open window
+            self.window_detectors.entry(device_id).or_default().restart();
+        }
 
-        // Reporting for Netdata collector
-        let tmp_file_path_str = format!("{}/new{}", NETDATA_PATH_PREFIX, device_id);
-        let current_file_path_str = format!("{}/current{}", NETDATA_PATH_PREFIX, device_id);
-        let humidity_file_path_str = format!("{}/humidity{}", NETDATA_PATH_PREFIX, device_id);
+        let current_time = Local::now();
+        let current_timestamp = current_time.timestamp();
 
-        // Write temperature and target
-        match File::create(&tmp_file_path_str) {
-            Ok(mut file) => {
-                if let Err(e) = writeln!(file, "SET temperature = {:.0}", temp * 10.0) {
-                     eprintln!("Error writing temperature to {}: {}", tmp_file_path_str, e);
-                }
-                if let Err(e) = writeln!(file, "SET target = {:.0}", target_temp * 10.0) {
-                    eprintln!("Error writing target to {}: {}", tmp_file_path_str, e);
-                }
-                // C++ dprintf, then close, then rename. Rust write, then rename.
-                drop(file); // Ensure file is closed before rename
-                if let Err(e) = rename(&tmp_file_path_str, &current_file_path_str) {
-                    eprintln!("Error renaming {} to {}: {}", tmp_file_path_str, current_file_path_str, e);
-                }
+        let mut heater_on = false;
+        let mut protection = None;
+
+        let mode = self.room_mode(device_id, current_timestamp);
+        let force_heat = matches!(mode, Some(RoomMode::ForceHeat { .. }));
+        let target_temp = self.room_target(device_id, current_time);
+
+        self.last_temp_deci.insert(device_id, temp);
+
+        let is_disabled = self.is_heater_disabled(device_id, current_timestamp);
+        let future_target_temp = self.room_target(device_id, current_time + chrono::Duration::minutes(10));
+
+        let window_open = self.check_open_window(device_id, current_timestamp, temp, humidity);
+        self.fault_detectors.entry(device_id).or_default().temperature(temp);
+        self.check_faults(device_id, current_timestamp);
+
+        if let Some(control_strategy) = self.controls.get_mut(device_id as usize) {
+            // Readings with an open window or forced heat would teach the strategy a wrong offset
+            control_strategy.set_learning(!window_open && !force_heat);
+            let decision = control_strategy.get_mode(
+                temp,
+                target_temp,
+                future_target_temp,
+                current_time
+            );
+            let (mode_on, delay_ms) = (decision.on, decision.delay_ms);
+            // Call set_output on the control strategy object itself (for its internal state)
+            control_strategy.set_output(mode_on, delay_ms, current_time);
+            self.record_decision(device_id, temp, target_temp, &decision, is_disabled);
+
+            // If delay is not zero, than mode_on is still opposite for now
+            heater_on = mode_on ^ (delay_ms != 0);
+
+            // Now, command the actual relay, every reading sends its command
+            let request = RelayRequest { on: mode_on, delay_ms, force_heat, paused: is_disabled || window_open, manual: false };
+            let Some(RelayOutcome { on: command_on, delay_ms: command_delay_ms, safety, protection: reason, unconfirmed, relay_on, .. }) =
+                self.drive_relay(device_id, Some(temp), request, current_time, false).await else {
+                unreachable!("commands are always sent without only_changes");
+            };
+            protection = reason;
+            if safety.is_some() || protection.is_some() || window_open || force_heat {
+                heater_on = command_on ^ (command_delay_ms != 0);
             }
-            Err(e) => eprintln!("Error creating {}: {}", tmp_file_path_str, e),
+
+            let diagnostics = &decision.diagnostics;
+            info!(
+                device_id,
+                room = room_name(device_id),
+                source = %src.ip(),
+                raw_temp = round2(raw_temp),
+                sensors = ?fused.sensors,
+                humidity = round2(humidity),
+                temp = round2(temp),
+                target = round2(target_temp),
+                decision = if mode_on { "ON" } else { "OFF" },
+                delay_min = round2(delay_ms as f64 / 60_000.0),
+                relay_on,
+                disabled = is_disabled,
+                window_open,
+                mode = ?mode,
+                unconfirmed,
+                reason = ?diagnostics.reason,
+                dt = diagnostics.dt.map(round2),
+                offset = diagnostics.offset.map(round2),
+                pulse_width = diagnostics.pulse_width.map(round2),
+                minutes_in_state = diagnostics.minutes_in_state.map(round2),
+                safety = ?safety,
+                protection = ?protection,
+                "sensor report"
+            );
+        } else {
+            warn!(device_id, temp = round2(temp), target = round2(target_temp), "no control strategy for sensor");
         }
+        // Update web state after processing the report
+        self.update_history(device_id, TemperaturePoint {
+            timestamp: current_timestamp,
+            temperature: temp,
+            target: target_temp,
+            heater_on,
+            is_disabled,
+            protection,
+            mode: mode.map(|mode| mode.action()),
+        }).await?;
+        write_netdata(device_id, temp, target_temp, humidity);
+        Ok(())
+    }
 
-        // Write humidity
-        match File::create(&tmp_file_path_str) { // Reuse tmp_file_path_str for humidity
-            Ok(mut file) => {
-                 if let Err(e) = writeln!(file, "SET humidity = {:.0}", humidity * 10.0) {
-                     eprintln!("Error writing humidity to {}: {}", tmp_file_path_str, e);
-                 }
-                 drop(file);
-                 if let Err(e) = rename(&tmp_file_path_str, &humidity_file_path_str) {
-                    eprintln!("Error renaming {} to {}: {}", tmp_file_path_str, humidity_file_path_str, e);
-                 }
+    // Diag reply of all rooms that have devices, rendered with the [diag] templates
+    async fn format_diag(&mut self, src: SocketAddr) -> Result<()> {
+        info!(source = %src, "diag request");
+        let now = Local::now();
+
+        let mut rooms = Vec::new();
+        let mut failures = Vec::new();
+        for room_id in 0..ROOM_NAMES.len() as u32 {
+            let room = room_name(room_id);
+            let sensors = self.room_fusion(room_id).sensors;
+            let relay = self.devices.room_relay(room).map(|relay| relay.id);
+            if sensors.is_empty() && relay.is_none() {
+                continue;
+            }
+            let mut modes = Vec::new();
+            if self.is_heater_disabled(room_id, now.timestamp()) {
+                modes.push("disabled".to_string());
+            }
+            if self.in_failsafe.contains(&room_id) {
+                modes.push("failsafe".to_string());
+            }
+            if self.window_detectors.get(&room_id).is_some_and(|detector| detector.is_paused(now.timestamp())) {
+                modes.push("window".to_string());
             }
-            Err(e) => eprintln!("Error creating {}: {}", tmp_file_path_str, e),
+            match self.room_modes.get(&room_id).filter(|mode| !mode.expired(now.timestamp())) {
+                Some(RoomMode::ForceHeat { .. }) => modes.push("force".to_string()),
+                Some(RoomMode::Boost { level, .. }) => modes.push(format!("boost{}", level)),
+                Some(RoomMode::Away { .. }) => modes.push("away".to_string()),
+                None => {}
+            }
+            rooms.push(RoomDiag {
+                room: room.to_string(),
+                temp: self.last_temp_deci.get(&room_id).copied(),
+                target: self.room_target(room_id, now),
+                relay_on: self.last_relay_on_status.get(&room_id).copied(),
+                modes,
+            });
+
+            let failed_sensors = sensors.into_iter()
+                .filter(|&sensor| !self.is_available(DeviceKind::Sensor, sensor))
+                .map(|sensor| (DeviceKind::Sensor, sensor));
+            let failed_relay = relay
+                .filter(|&relay| !self.is_available(DeviceKind::Relay, relay))
+                .map(|relay| (DeviceKind::Relay, relay));
+            failures.extend(failed_sensors.chain(failed_relay)
+                .map(|(kind, id)| FailedDevice { room: room.to_string(), kind, id }));
         }
+        let diag_message = self.config.diag.render(now, &rooms, &failures);
 
-        println!(); // End the line for sensor report
-        stdout().flush()?;
-        self.update_web_state().await;
+        // Send the diagnostic message back to src
+        // Short replies stay plain text, longer ones are framed like device messages.
+        // Framed replies start with a byte that is never valid UTF-8.
+        let packets = if diag_message.len() <= MAX_UDP {
+            vec![diag_message.into_bytes()]
+        } else {
+            self.diag_seq = self.diag_seq.wrapping_add(1);
+            fragment(self.diag_seq, diag_message.as_bytes())?
+        };
+        for packet in packets {
+            if let Err(e) = self.sender.send_to(&packet, src).await {
+                warn!(source = %src, error = %e, "failed to send diag reply");
+                break;
+            }
+        }
         Ok(())
     }
+}
 
-    fn format_diag(&self, src: SocketAddr) -> Result<()> {
-        let current_time = Local::now();
-        println!(
-            "{} Diag request from {}",
-            current_time.format("%Y-%m-%d %H:%M:%S"),
-            src
-        );
-
-        let temp0_str = self.last_temp_deci.get(&0).map_or_else(|| "N/A".to_string(), |t| format!("{:.1}", t));
-        let relay0_on_str = self.last_relay_on_status.get(BEDROOM_RELAY_EXPECTED_IP).map_or_else(|| "", |&on| if on { " [ON]" } else { "" });
-
-        let temp2_str = self.last_temp_deci.get(&2).map_or_else(|| "N/A".to_string(), |t| format!("{:.1}", t));
-        let relay2_on_str = self.last_relay_on_status.get(KIDS_RELAY_EXPECTED_IP).map_or_else(|| "", |&on| if on { " [ON]" } else { "" });
+// Work for the control task, besides web commands and its timer
+enum ServerEvent {
+    // Reassembled by the UDP ingestion task
+    Message { src: SocketAddr, msg: DeviceMessage },
+    // Answered once relays are in their shutdown state and the state is saved
+    Shutdown { signal: &'static str, done: oneshot::Sender<()> },
+}
 
-        let mut diag_message = format!("Temp0: {}{}, Temp2: {}{}", temp0_str, relay0_on_str, temp2_str, relay2_on_str);
+// Device messages to wait for while the control task is busy, more are held back in the socket
+const EVENT_QUEUE: usize = 64;
 
-        let now_ts = current_time.timestamp();
-        let staleness_threshold = 180; // 3 minutes
+// UDP ingestion: hands every device message to the control task
+struct Ingestion(mpsc::Sender<ServerEvent>);
 
-        if now_ts - self.last_message_timestamp.get(BEDROOM_SENSOR_EXPECTED_IP).cloned().unwrap_or(0) > staleness_threshold {
-            diag_message += "\nFAIL: Bedroom sensor";
-        } else if now_ts - self.last_message_timestamp.get(BEDROOM_RELAY_EXPECTED_IP).cloned().unwrap_or(0) > staleness_threshold {
-            diag_message += "\nFAIL: Bedroom relay";
-        }
+impl MessageHandler<DeviceMessage> for Ingestion {
+    async fn on_message(&mut self, src: SocketAddr, msg: DeviceMessage) -> anyhow::Result<()> {
+        self.0.send(ServerEvent::Message { src, msg }).await
+            .map_err(|_| anyhow::anyhow!("control task stopped"))
+    }
+}
 
-        if now_ts - self.last_message_timestamp.get(KIDS_SENSOR_EXPECTED_IP).cloned().unwrap_or(0) > staleness_threshold {
-            diag_message += "\nFAIL: Kids sensor";
-        } else if now_ts - self.last_message_timestamp.get(KIDS_RELAY_EXPECTED_IP).cloned().unwrap_or(0) > staleness_threshold {
-            diag_message += "\nFAIL: Kids relay";
+// The only owner of the Server: device messages, web commands and periodic checks run
+// here one at a time, so strategies, relay guards and confirmations see every command
+async fn control_task(mut server: Server, mut events: mpsc::Receiver<ServerEvent>, mut commands: mpsc::Receiver<WebCommand>,
+    liveness: Arc<Liveness>, check_interval: Duration, tick_interval: Option<Duration>, status_interval: Option<Duration>) {
+    let mut interval = tokio::time::interval(check_interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut control_tick = tokio::time::interval(tick_interval.unwrap_or(Duration::from_secs(3600)));
+    control_tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut status_tick = tokio::time::interval(status_interval.unwrap_or(Duration::from_secs(3600)));
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                liveness.event_started(Local::now().timestamp());
+                server.check().await;
+                liveness.event_done();
+                liveness.checked(Local::now().timestamp());
+            }
+            _ = control_tick.tick(), if tick_interval.is_some() => {
+                liveness.event_started(Local::now().timestamp());
+                server.control_tick().await;
+                liveness.event_done();
+            }
+            _ = status_tick.tick(), if status_interval.is_some() => {
+                systemd::notify(&format!("STATUS={}", server.status_line()));
+            }
+            Some(command) = commands.recv() => {
+                liveness.event_started(Local::now().timestamp());
+                server.web_command(command).await;
+                liveness.event_done();
+            }
+            event = events.recv() => match event {
+                Some(ServerEvent::Message { src, msg }) => {
+                    liveness.event_started(Local::now().timestamp());
+                    if let Err(e) = server.on_message(src, msg).await {
+                        warn!(source = %src, "Error handling message: {:?}", e);
+                    }
+                    liveness.event_done();
+                }
+                Some(ServerEvent::Shutdown { signal, done }) => {
+                    server.shutdown(signal).await;
+                    let _ = done.send(());
+                    return;
+                }
+                None => return,
+            },
         }
+    }
+}
 
-        // Send the diagnostic message back to src
-        // The C++ Relay::send_message is more complex (hostname resolution).
-        // Here, src is already a SocketAddr.
-        let udp_socket = UdpSocket::bind("0.0.0.0:0") // Bind to any available local port
-            .context("Failed to bind UDP socket for diagnostics")?;
-
-        match udp_socket.send_to(diag_message.as_bytes(), src) {
-            Ok(_) => { /* Successfully sent */ }
-            Err(e) => {
-                print!(" [NDIAG_SEND_ERR: {}] ", e); // C++ prints "[NDIAG]"
-                stdout().flush()?;
+// Watchdog pings while the control task makes progress
+async fn notify_watchdog(liveness: Arc<Liveness>, config: SystemdConfig, check_interval: Duration) {
+    let Some(watchdog) = systemd::watchdog_interval() else {
+        return;
+    };
+    let mut watchdog_tick = tokio::time::interval(watchdog);
+    loop {
+        watchdog_tick.tick().await;
+        let now = Local::now().timestamp();
+        match liveness.stalled(now, config.stall_sec, check_interval.as_secs() as i64) {
+            None => {
+                systemd::notify("WATCHDOG=1");
             }
+            Some(stuck) => warn!(stuck, "no progress, watchdog ping skipped"),
         }
-        Ok(())
     }
 }
 
@@ -518,17 +1628,19 @@ impl MessageHandler<DeviceMessage> for Server {
             self.new_relay_report(src, relay_report).await?;
             known_message_component_found = true;
         } else if msg.format_diag() {
-            self.format_diag(src)?;
+            self.format_diag(src).await?;
+            known_message_component_found = true;
+        } else if msg.has_heat_on() {
+            // Demand of another controller, e.g. a second server for other rooms
+            debug!(source = %src, heat_on = msg.heat_on(), "heat demand message");
+            self.external_demand.insert(src.ip(), (msg.heat_on(), Local::now().timestamp()));
+            self.update_heat_demand().await;
+            self.update_web_state().await;
             known_message_component_found = true;
         }
 
         if !known_message_component_found {
-            println!(
-                "{} Unknown message type from {} (or empty message components). Message: {:?}",
-                Local::now().format("%Y-%m-%d %H:%M:%S"),
-                src,
-                msg
-            );
+            warn!(source = %src, message = ?msg, "unknown message type (or empty message components)");
         }
         Ok(())
     }
@@ -536,16 +1648,147 @@ impl MessageHandler<DeviceMessage> for Server {
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    let args = Args::parse();
+    if args.hash_password {
+        let mut password = String::new();
+        std::io::stdin().read_line(&mut password)?;
+        println!("password_hash = \"{}\"", auth::hash_password(password.trim_end_matches(['\r', '\n']))?);
+        return Ok(());
+    }
+    if args.new_token {
+        let (token, hash) = auth::new_token();
+        println!("Token: {}", token);
+        println!("token_sha256 = \"{}\"", hash);
+        return Ok(());
+    }
+    let mut config = Config::load(args.config.as_deref())?;
+    if args.static_dir.is_some() {
+        config.web.static_dir = args.static_dir;
+    }
+    logging::init(&config.log)?;
+    if config.web.tls.is_none() && config.auth.secure_cookies && !config.auth.users.is_empty() {
+        warn!("secure_cookies is set without TLS, browsers will only keep the session on localhost");
+    }
+    let web_config = std::mem::take(&mut config.web);
+    let auth = Arc::new(Auth::new(std::mem::take(&mut config.auth)));
+    let audit = Arc::new(AuditLog::new(std::mem::take(&mut config.audit)));
+    let mut devices = std::mem::take(&mut config.devices);
+    let defaults = default_devices();
+    if devices.sensors.is_empty() {
+        devices.sensors = defaults.sensors;
+    }
+    if devices.relays.is_empty() {
+        devices.relays = defaults.relays;
+    }
+    // Devices of other rooms would never be controlled
+    let device_rooms = devices.sensors.iter().filter_map(|sensor| sensor.room.as_deref())
+        .chain(devices.relays.iter().map(|relay| relay.room.as_str()));
+    for room in device_rooms {
+        if room_index(room).is_none() {
+            anyhow::bail!("Unknown room {} in [devices], expected one of {}", room, ROOM_NAMES.join(", "));
+        }
+    }
+    let devices = Arc::new(DeviceRegistry::new(devices));
+    let inventory = Arc::new(DeviceInventory::default());
+    let mut calibration = std::mem::take(&mut config.calibration);
+    // Every room sensor can be calibrated, built-in offsets follow the firmware id
+    let room_sensors = ROOM_NAMES.iter().flat_map(|room| devices.room_sensors(room));
+    let fused_sensors = config.sensor_fusion.rooms.values().flat_map(|room| room.sensors.clone());
+    for device_id in room_sensors.chain(fused_sensors) {
+        let temp_offset = CORRECTION.get(device_id as usize).copied().unwrap_or(0.0);
+        calibration.sensors.entry(devices.sensor_name(device_id))
+            .or_insert(SensorCalibration { temp_offset, ..Default::default() });
+    }
+    let calibrator = Arc::new(Calibrator::new(calibration));
+    let notifiers = alert::spawn_notifiers(std::mem::take(&mut config.alert.notifiers));
+    let alerts = AlertManager::new(&config.alert, notifiers);
+    if !auth.enabled() {
+        warn!("no users configured, web API is open to everyone");
+    }
+
     // Initialize the server state
-    let mut server = Server::new();
+    let check_interval = Duration::from_secs(config.failsafe.check_interval_sec.max(1));
+    let tick_interval = (config.control.tick_sec != 0).then(|| Duration::from_secs(config.control.tick_sec));
+    let shutdown_timeout = Duration::from_secs(config.shutdown.timeout_sec);
+    let systemd_config = std::mem::take(&mut config.systemd);
+    let sender = Arc::new(UdpSender::bind().await.context("Failed to bind the outgoing UDP socket")?);
+    // Host names resolve in the background, commands to a host fail until it is resolved
+    for relay in devices.relays() {
+        sender.prefetch_relay(&relay.host);
+    }
+    if let Some(relay) = &config.heat_demand.relay {
+        sender.prefetch_relay(relay);
+    }
+    for target in &config.heat_demand.publish {
+        sender.prefetch(target);
+    }
+    let mut server = Server::new(audit.clone(), alerts, calibrator.clone(), devices.clone(), inventory.clone(),
+        sender, config);
+    server.restore_state().await;
     let web_state = server.web_state.clone();
+    let events = server.events.clone();
+
+    // Sockets passed by systemd socket activation replace the configured addresses
+    let mut activated = systemd::listen_fds();
+    let listeners = web::bind(&web_config, &mut activated).await?;
+    let udp_socket = match activated.remove("udp") {
+        Some(fd) => {
+            let socket = UdpSocket::from(fd);
+            socket.set_nonblocking(true)?;
+            tokio::net::UdpSocket::from_std(socket)?
+        }
+        None => tokio::net::UdpSocket::bind("0.0.0.0:4000").await.context("Failed to bind 0.0.0.0:4000")?,
+    };
+    for name in activated.keys() {
+        warn!(name, "ignoring a socket passed by systemd, expected udp, http or https");
+    }
 
     // Start the web server in a separate task
-    tokio::spawn(async move {
-        create_web_server(web_state).await;
+    let (commands, command_receiver) = mpsc::channel(EVENT_QUEUE);
+    let web_state = WebState { server_state: web_state, events, auth, audit: audit.clone(), calibrator, devices, inventory, commands };
+    let (stop_web, web_shutdown) = watch::channel(false);
+    let web_server = tokio::spawn(async move {
+        if let Err(e) = create_web_server(web_config, web_state, listeners, web_shutdown).await {
+            error!("Web server failed: {:?}", e);
+        }
     });
 
-    // Start the main loop using FragmentCombiner
-    println!("Starting temperature server on 0.0.0.0:4000...");
-    FragmentCombiner::new(&mut server).main_loop("0.0.0.0:4000").await
+    // Control task, fed by the UDP ingestion below and the web server
+    let liveness = Arc::new(Liveness::default());
+    liveness.checked(Local::now().timestamp());
+    let systemd_enabled = systemd::notify("READY=1");
+    let status_interval = systemd_enabled.then(|| Duration::from_secs(systemd_config.status_sec.max(1)));
+    let (event_sender, event_receiver) = mpsc::channel(EVENT_QUEUE);
+    let mut control = tokio::spawn(control_task(server, event_receiver, command_receiver, liveness.clone(),
+        check_interval, tick_interval, status_interval));
+    if systemd_enabled {
+        tokio::spawn(notify_watchdog(liveness, systemd_config, check_interval));
+    }
+
+    // UDP ingestion using FragmentCombiner, until SIGTERM or SIGINT
+    let mut terminate = signal(SignalKind::terminate()).context("Failed to install SIGTERM handler")?;
+    info!("Starting temperature server on {}...", udp_socket.local_addr()?);
+    let mut ingestion = Ingestion(event_sender.clone());
+    let mut combiner = FragmentCombiner::new(&mut ingestion);
+    let received = tokio::select! {
+        result = combiner.serve(udp_socket) => return result,
+        result = &mut control => anyhow::bail!("control task stopped: {:?}", result),
+        _ = terminate.recv() => "SIGTERM",
+        _ = tokio::signal::ctrl_c() => "SIGINT",
+    };
+
+    // No more device messages or web connections, the control task finishes the queued ones
+    systemd::notify("STOPPING=1");
+    let _ = stop_web.send(true);
+    let (done, shut_down) = oneshot::channel();
+    if event_sender.send(ServerEvent::Shutdown { signal: received, done }).await.is_ok()
+        && tokio::time::timeout(shutdown_timeout, shut_down).await.is_err() {
+        warn!(timeout_sec = shutdown_timeout.as_secs(), "control task did not finish the shutdown, exiting anyway");
+    }
+    if tokio::time::timeout(shutdown_timeout, web_server).await.is_err() {
+        warn!(timeout_sec = shutdown_timeout.as_secs(), "web connections still open, closing them");
+    }
+    audit.flush();
+    info!("shutdown complete");
+    Ok(())
 }
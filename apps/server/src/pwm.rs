@@ -1,6 +1,51 @@
 use chrono::{DateTime, Local, Duration, TimeZone};
+use serde::{Deserialize, Serialize};
 use std::f64;
 
+// Which rule produced the last decision
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DecisionReason {
+    #[default]
+    None,
+    // SimpleControl: above, below or inside the hysteresis band
+    TooWarm,
+    TooCold,
+    InBand,
+    // PWMControl: far enough from target to keep the current state
+    StayOn,
+    StayOff,
+    // PWMControl: switch once the pulse width elapses
+    Pulse,
+    PulseElapsed,
+}
+
+// Reasoning behind the last get_mode() result, for logs and the audit trail
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ControlDiagnostics {
+    pub reason: DecisionReason,
+    // Smoothed temperature minus target
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dt: Option<f64>,
+    // Learned sensor offset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<f64>,
+    // Minutes the heater stays in the current state
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pulse_width: Option<f64>,
+    // Minutes the heater has been in the current state
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub minutes_in_state: Option<f64>,
+}
+
+// Requested heater state, applied after delay_ms
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Decision {
+    pub on: bool,
+    pub delay_ms: u32,
+    pub diagnostics: ControlDiagnostics,
+}
+
 pub trait Control {
     fn get_mode(
         &mut self,
@@ -8,36 +53,64 @@ pub trait Control {
         target_temp: f64,
         future_target_temp: f64,
         current_time: DateTime<Local>,
-    ) -> (bool, u32);
+    ) -> Decision;
 
     fn set_output(&mut self, mode_on: bool, delay_ms: u32, current_time: DateTime<Local>);
+
+    // Decision for the last reading with the current targets, without taking a new sample.
+    // Used by the periodic control tick, None before the first reading.
+    fn reevaluate(&mut self, _target_temp: f64, _future_target_temp: f64, _current_time: DateTime<Local>) -> Option<Decision> {
+        None
+    }
+
+    // Stops adapting learned parameters while readings are unrepresentative, e.g. open window
+    fn set_learning(&mut self, _enabled: bool) {}
+
+    // Learned offset, saved on shutdown and restored at startup
+    fn learned_offset(&self) -> Option<f64> {
+        None
+    }
+
+    fn restore_offset(&mut self, _offset: f64) {}
 }
 
 pub struct SimpleControl {
     is_on: bool,
+    last_temp: Option<f64>,
 }
 
 impl SimpleControl {
     pub fn new() -> Self {
-        Self { is_on: false }
+        Self { is_on: false, last_temp: None }
     }
 }
 
 impl Control for SimpleControl {
-    fn get_mode(&mut self, temp: f64, target: f64, _future_target: f64, _current_time: DateTime<Local>) -> (bool, u32) {
+    fn get_mode(&mut self, temp: f64, target: f64, _future_target: f64, _current_time: DateTime<Local>) -> Decision {
+        self.last_temp = Some(temp);
         let dt = temp - target;
-        if dt > 0.1 {
-            (false, 0)
+        let (on, reason) = if dt > 0.1 {
+            (false, DecisionReason::TooWarm)
         } else if dt < -0.1 {
-            (true, 0)
+            (true, DecisionReason::TooCold)
         } else {
-            (self.is_on, 0)
+            (self.is_on, DecisionReason::InBand)
+        };
+        Decision {
+            on,
+            delay_ms: 0,
+            diagnostics: ControlDiagnostics { reason, dt: Some(dt), ..Default::default() },
         }
     }
 
     fn set_output(&mut self, on: bool, _delay: u32, _current_time: DateTime<Local>) {
         self.is_on = on;
     }
+
+    fn reevaluate(&mut self, target: f64, future_target: f64, current_time: DateTime<Local>) -> Option<Decision> {
+        let temp = self.last_temp?;
+        Some(self.get_mode(temp, target, future_target, current_time))
+    }
 }
 
 pub struct PWMControl {
@@ -48,6 +121,7 @@ pub struct PWMControl {
     new_mode: bool,
     new_mode_time: DateTime<Local>,
     last_sensor_temp: f64,
+    learning: bool,
 }
 
 impl PWMControl {
@@ -61,6 +135,7 @@ impl PWMControl {
             new_mode: true,
             new_mode_time: epoch_time,
             last_sensor_temp: 0.0,
+            learning: true,
         }
     }
 
@@ -78,43 +153,31 @@ impl PWMControl {
         self.initial_offset = offset.clamp(-0.7, 0.3);
     }
 
-}
-
-impl Control for PWMControl {
-
-    fn get_mode(&mut self, temp: f64, target: f64, future_target: f64, current_time: DateTime<Local>) -> (bool, u32) {
-        self.last_sensor_temp = temp;
-
+    // The relay did the switch requested with a delay
+    fn apply_pending(&mut self, current_time: DateTime<Local>) {
         if current_time >= self.new_mode_time {
             if self.is_on != self.new_mode {
                 self.is_on_time = self.new_mode_time;
             }
             self.is_on = self.new_mode;
         }
+    }
 
-        let t_s = 0.5;
-        if self.smooth_t == -1.0 {
-            self.smooth_t = temp;
-        } else {
-            self.smooth_t = self.smooth_t * t_s + temp * (1.0 - t_s);
-        }
-
-        let above_target = self.smooth_t - future_target;
-        let offset = self.get_avg_offset();
+    fn decide(&self, above_target: f64, offset: f64, current_time: DateTime<Local>) -> Decision {
         let dt = above_target + offset;
-        print!("dt {:.2} offset={:.2} ", above_target, offset);
-
-        if future_target == target {
-            self.update_avg_offset(above_target, self.is_on);
-        }
+        let mut diagnostics = ControlDiagnostics {
+            dt: Some(above_target),
+            offset: Some(offset),
+            ..Default::default()
+        };
 
         if dt <= -0.9 && self.is_on {
-            print!("[1:inf] ");
-            return (true, 0);
+            diagnostics.reason = DecisionReason::StayOn;
+            return Decision { on: true, delay_ms: 0, diagnostics };
         }
         if dt >= -0.1 && !self.is_on {
-            print!("[0:inf] ");
-            return (false, 0);
+            diagnostics.reason = DecisionReason::StayOff;
+            return Decision { on: false, delay_ms: 0, diagnostics };
         }
 
         let duration_on_state = current_time.signed_duration_since(self.is_on_time);
@@ -125,27 +188,77 @@ impl Control for PWMControl {
         } else {
             (1.0 + dt) * 10.0
         };
+        diagnostics.pulse_width = Some(pulse_width);
+        diagnostics.minutes_in_state = Some(minutes);
 
         if minutes < pulse_width {
-            print!("[{}:{:.1} vs {:.1}] ", self.is_on as u8, minutes, pulse_width);
-            (!self.is_on, ((pulse_width - minutes) * 60000.0).max(0.0) as u32)
+            diagnostics.reason = DecisionReason::Pulse;
+            let delay_ms = ((pulse_width - minutes) * 60000.0).max(0.0) as u32;
+            Decision { on: !self.is_on, delay_ms, diagnostics }
         } else {
-            print!("[{}!]", (!self.is_on) as u8);
-            (!self.is_on, 0)
+            diagnostics.reason = DecisionReason::PulseElapsed;
+            Decision { on: !self.is_on, delay_ms: 0, diagnostics }
         }
     }
+}
+
+impl Control for PWMControl {
+
+    fn get_mode(&mut self, temp: f64, target: f64, future_target: f64, current_time: DateTime<Local>) -> Decision {
+        self.last_sensor_temp = temp;
+        self.apply_pending(current_time);
+
+        let t_s = 0.5;
+        if self.smooth_t == -1.0 {
+            self.smooth_t = temp;
+        } else {
+            self.smooth_t = self.smooth_t * t_s + temp * (1.0 - t_s);
+        }
+
+        let above_target = self.smooth_t - future_target;
+        let offset = self.get_avg_offset();
+
+        if future_target == target && self.learning {
+            self.update_avg_offset(above_target, self.is_on);
+        }
+        self.decide(above_target, offset, current_time)
+    }
 
     fn set_output(&mut self, on: bool, delay: u32, current_time: DateTime<Local>) {
-        if delay < 60_000 {
-            self.new_mode = on;
-            self.new_mode_time = current_time + Duration::milliseconds(delay as i64);
+        // Delays of any length count: the control tick re-evaluates once the relay did the
+        // switch, so pulses longer than a reading interval end on time. A later command
+        // replaces a switch before it is due, like on the relay.
+        self.new_mode = on;
+        self.new_mode_time = current_time + Duration::milliseconds(delay as i64);
+    }
+
+    fn reevaluate(&mut self, _target: f64, future_target: f64, current_time: DateTime<Local>) -> Option<Decision> {
+        if self.smooth_t == -1.0 {
+            return None;
         }
+        self.apply_pending(current_time);
+        let offset = self.get_avg_offset();
+        Some(self.decide(self.smooth_t - future_target, offset, current_time))
+    }
+
+    fn set_learning(&mut self, enabled: bool) {
+        self.learning = enabled;
+    }
+
+    fn learned_offset(&self) -> Option<f64> {
+        Some(self.get_avg_offset())
+    }
+
+    fn restore_offset(&mut self, offset: f64) {
+        // Same bounds as learning, a state file from an older version can't break control
+        self.initial_offset = offset.clamp(-0.7, 0.3);
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::relay_guard::{RelayCommand, RelayGuard, RelayProtectionConfig};
     use chrono::{Duration, Local, TimeZone};
 
 
@@ -280,7 +393,8 @@ mod tests {
                 room.update(mode, current_time.signed_duration_since(old_time).num_milliseconds() as f64);
             }
 
-            let (new_mode, delay_ms_u32) = pwm.get_mode(room.get_sensor_t(), curr_target, curr_target, current_time);
+            let decision = pwm.get_mode(room.get_sensor_t(), curr_target, curr_target, current_time);
+            let (new_mode, delay_ms_u32) = (decision.on, decision.delay_ms);
             pwm.set_output(new_mode, delay_ms_u32, current_time);
 
             req_mode = new_mode;
@@ -301,4 +415,66 @@ mod tests {
         let avg_err = total_error / total_samples;
         assert!(avg_err > 0.22 && avg_err < 0.25, "Average error: {:.4}", avg_err);
     }
+
+    #[test]
+    fn delayed_switch_and_reevaluate() {
+        let start = Local.timestamp_millis_opt(10_000_000).unwrap();
+        let minutes = |m: i64| start + Duration::minutes(m);
+        let mut pwm = PWMControl::new(0.0);
+        pwm.set_learning(false);
+        assert_eq!(pwm.reevaluate(20.0, 20.0, start), None);
+
+        // 0.5°C below target after a long OFF time: ON now, for a 5 minute pulse
+        pwm.set_output(false, 0, Local.timestamp_opt(0, 0).unwrap());
+        let decision = pwm.get_mode(19.5, 20.0, 20.0, start);
+        assert_eq!((decision.on, decision.delay_ms, decision.diagnostics.reason), (true, 0, DecisionReason::PulseElapsed));
+        pwm.set_output(decision.on, decision.delay_ms, start);
+        let decision = pwm.reevaluate(20.0, 20.0, minutes(1)).unwrap();
+        assert_eq!((decision.on, decision.delay_ms), (false, 240_000));
+        pwm.set_output(decision.on, decision.delay_ms, minutes(1));
+
+        // Not due yet, the heater is still ON
+        let decision = pwm.reevaluate(20.0, 20.0, minutes(3)).unwrap();
+        assert_eq!((decision.on, decision.delay_ms), (false, 120_000));
+        // The relay switched OFF at 5 minutes, the OFF pulse is timed from then
+        let decision = pwm.reevaluate(20.0, 20.0, minutes(6)).unwrap();
+        assert_eq!((decision.on, decision.delay_ms, decision.diagnostics.reason), (true, 240_000, DecisionReason::Pulse));
+
+        // A later command replaces the pending switch, the heater stayed OFF
+        pwm.set_output(true, 240_000, minutes(6));
+        pwm.set_output(false, 0, minutes(7));
+        let decision = pwm.reevaluate(20.0, 20.0, minutes(11)).unwrap();
+        assert_eq!((decision.on, decision.delay_ms), (true, 0));
+
+        let mut simple = SimpleControl::new();
+        assert_eq!(simple.reevaluate(20.0, 20.0, start), None);
+        assert!(simple.get_mode(19.0, 20.0, 20.0, start).on);
+        // Schedule changed without a new reading
+        assert_eq!(simple.reevaluate(18.5, 18.5, minutes(1)).unwrap().diagnostics.reason, DecisionReason::TooWarm);
+    }
+    #[test]
+    fn long_delay_is_sent() {
+        let start = Local.timestamp_millis_opt(10_000_000).unwrap();
+        let mut pwm = PWMControl::new(0.0);
+        pwm.set_learning(false);
+        pwm.set_output(true, 0, start);
+
+        // 0.5°C below target right after switching ON: OFF after the 5 minute pulse
+        let decision = pwm.get_mode(19.5, 20.0, 20.0, start + Duration::minutes(1));
+        assert_eq!((decision.on, decision.delay_ms), (false, 240_000));
+
+        // Relay protection passes the delay unchanged, the relay gets it as decided
+        let limits = RelayProtectionConfig::default().limits("bedroom");
+        let mut guard = RelayGuard::default();
+        guard.record(RelayCommand { on: true, delay_ms: 0 }, start.timestamp_millis());
+        let now_ms = (start + Duration::minutes(1)).timestamp_millis();
+        let command = RelayCommand { on: decision.on, delay_ms: decision.delay_ms };
+        assert_eq!(guard.apply(&limits, command, now_ms), (command, None));
+        assert_eq!(guard.pending(now_ms), Some((false, now_ms + 240_000)));
+
+        // The strategy follows the switch the relay does by itself
+        pwm.set_output(command.on, command.delay_ms, start + Duration::minutes(1));
+        let decision = pwm.reevaluate(20.0, 20.0, start + Duration::minutes(6)).unwrap();
+        assert!(decision.on);
+    }
 }
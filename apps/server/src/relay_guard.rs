@@ -0,0 +1,350 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RelayProtectionConfig {
+    // Shortest time the heater stays ON once switched on
+    pub min_on_sec: u32,
+    // Shortest time the heater stays OFF once switched off
+    pub min_off_sec: u32,
+    // Further switches within an hour are refused, 0 means no limit
+    pub max_switches_per_hour: u32,
+    // Per room name overrides
+    pub rooms: HashMap<String, RoomProtection>,
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct RoomProtection {
+    pub min_on_sec: Option<u32>,
+    pub min_off_sec: Option<u32>,
+    pub max_switches_per_hour: Option<u32>,
+}
+
+impl Default for RelayProtectionConfig {
+    fn default() -> Self {
+        RelayProtectionConfig { min_on_sec: 180, min_off_sec: 180, max_switches_per_hour: 0, rooms: HashMap::new() }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RelayLimits {
+    pub min_on_sec: u32,
+    pub min_off_sec: u32,
+    pub max_switches_per_hour: u32,
+}
+
+impl RelayProtectionConfig {
+    pub fn limits(&self, room: &str) -> RelayLimits {
+        let room = self.rooms.get(room).copied().unwrap_or_default();
+        RelayLimits {
+            min_on_sec: room.min_on_sec.unwrap_or(self.min_on_sec),
+            min_off_sec: room.min_off_sec.unwrap_or(self.min_off_sec),
+            max_switches_per_hour: room.max_switches_per_hour.unwrap_or(self.max_switches_per_hour),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProtectionReason {
+    // Switch OFF postponed until the heater was ON for min_on_sec
+    MinOnTime,
+    // Switch ON postponed until the heater was OFF for min_off_sec
+    MinOffTime,
+    // Switch refused, max_switches_per_hour reached. Switching OFF is still allowed.
+    SwitchRate,
+}
+
+// Relay command as sent to the device: switch to `on` after `delay_ms`,
+// until then the relay is in the opposite state
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RelayCommand {
+    pub on: bool,
+    pub delay_ms: u32,
+}
+
+impl RelayCommand {
+    // Relay state right after sending the command
+    fn state_now(&self) -> bool {
+        self.on ^ (self.delay_ms != 0)
+    }
+}
+
+// Tracks the commanded relay state of one room, timestamps are unix milliseconds
+#[derive(Default, Clone)]
+pub struct RelayGuard {
+    on: bool,
+    last_switch_ms: Option<i64>,
+    switches_ms: VecDeque<i64>,
+    // Delayed switch the relay will do by itself, replaced by every new command
+    pending: Option<(bool, i64)>,
+}
+
+impl RelayGuard {
+    // Adjusts a strategy command to the limits. The result is what should be sent.
+    pub fn apply(&mut self, limits: &RelayLimits, command: RelayCommand, now_ms: i64) -> (RelayCommand, Option<ProtectionReason>) {
+        self.advance(now_ms);
+        let hold = RelayCommand { on: self.on, delay_ms: 0 };
+
+        let state_now = command.state_now();
+        // Only switches to ON count against the rate, switching OFF is always allowed
+        let turns_on = if self.on { command.on && command.delay_ms != 0 } else { state_now || command.delay_ms != 0 };
+        if turns_on && self.rate_exceeded(limits, now_ms) {
+            return self.refuse(limits, now_ms);
+        }
+        if state_now != self.on {
+            // Immediate switch
+            let wait_ms = self.remaining_ms(limits, now_ms);
+            if wait_ms > 0 {
+                let adjusted = if command.delay_ms == 0 {
+                    // Switch as soon as allowed
+                    RelayCommand { on: command.on, delay_ms: wait_ms as u32 }
+                } else {
+                    hold
+                };
+                return self.finish(adjusted, Some(self.min_time_reason()), now_ms);
+            }
+            // For a pulse, the switch back waits for the minimum time of the state entered
+            let min_ms = self.min_ms(limits, state_now);
+            if command.delay_ms != 0 && (command.delay_ms as i64) < min_ms {
+                let adjusted = RelayCommand { on: command.on, delay_ms: min_ms as u32 };
+                let reason = if state_now { ProtectionReason::MinOnTime } else { ProtectionReason::MinOffTime };
+                return self.finish(adjusted, Some(reason), now_ms);
+            }
+            return self.finish(command, None, now_ms);
+        }
+
+        if command.delay_ms != 0 {
+            // Delayed switch to the opposite state
+            let wait_ms = self.remaining_ms(limits, now_ms);
+            if wait_ms > command.delay_ms as i64 {
+                let adjusted = RelayCommand { on: command.on, delay_ms: wait_ms as u32 };
+                return self.finish(adjusted, Some(self.min_time_reason()), now_ms);
+            }
+        }
+        self.finish(command, None, now_ms)
+    }
+
+    // Relay state expected from the commands sent so far
+    pub fn state(&mut self, now_ms: i64) -> bool {
+        self.advance(now_ms);
+        self.on
+    }
+
+    // Delayed switch still to come: the state and when the relay does it
+    pub fn pending(&self, now_ms: i64) -> Option<(bool, i64)> {
+        self.pending.filter(|&(_, at_ms)| at_ms > now_ms)
+    }
+
+    // Whether sending the command changes the relay state or its pending switch.
+    // Recomputed delays of the same switch within a minute count as unchanged.
+    pub fn changes(&mut self, command: RelayCommand, now_ms: i64) -> bool {
+        self.advance(now_ms);
+        if command.state_now() != self.on {
+            return true;
+        }
+        let pending = (command.delay_ms != 0).then(|| (command.on, now_ms + command.delay_ms as i64));
+        match (pending, self.pending) {
+            (Some((on, at_ms)), Some((pending_on, pending_at_ms))) => on != pending_on || (at_ms - pending_at_ms).abs() > 60_000,
+            (pending, current) => pending.is_some() != current.is_some(),
+        }
+    }
+
+    // Records a command sent without limits, e.g. from a safety override
+    pub fn record(&mut self, command: RelayCommand, now_ms: i64) {
+        self.advance(now_ms);
+        self.finish(command, None, now_ms);
+    }
+
+    fn finish(&mut self, command: RelayCommand, reason: Option<ProtectionReason>, now_ms: i64) -> (RelayCommand, Option<ProtectionReason>) {
+        let state_now = command.state_now();
+        if state_now != self.on {
+            self.switch(state_now, now_ms);
+        }
+        self.pending = (command.delay_ms != 0).then(|| (command.on, now_ms + command.delay_ms as i64));
+        (command, reason)
+    }
+
+    // Applies a delayed switch the relay has done since the last command
+    fn advance(&mut self, now_ms: i64) {
+        if let Some((on, at_ms)) = self.pending {
+            if at_ms <= now_ms {
+                self.pending = None;
+                if on != self.on {
+                    self.switch(on, at_ms);
+                }
+            }
+        }
+        while self.switches_ms.front().is_some_and(|&at_ms| at_ms <= now_ms - 3_600_000) {
+            self.switches_ms.pop_front();
+        }
+    }
+
+    // Over the switch rate an OFF heater stays OFF. A pulse from ON keeps only its switch
+    // OFF, after the minimum on-time, so a refused switch never keeps the heater running.
+    fn refuse(&mut self, limits: &RelayLimits, now_ms: i64) -> (RelayCommand, Option<ProtectionReason>) {
+        let delay_ms = if self.on { self.remaining_ms(limits, now_ms) as u32 } else { 0 };
+        self.finish(RelayCommand { on: false, delay_ms }, Some(ProtectionReason::SwitchRate), now_ms)
+    }
+
+    fn switch(&mut self, on: bool, at_ms: i64) {
+        self.on = on;
+        self.last_switch_ms = Some(at_ms);
+        self.switches_ms.push_back(at_ms);
+    }
+
+    fn rate_exceeded(&self, limits: &RelayLimits, now_ms: i64) -> bool {
+        limits.max_switches_per_hour != 0
+            && self.switches_ms.iter().filter(|&&at_ms| at_ms > now_ms - 3_600_000).count() >= limits.max_switches_per_hour as usize
+    }
+
+    // Time left before the current state may change
+    fn remaining_ms(&self, limits: &RelayLimits, now_ms: i64) -> i64 {
+        let Some(last_switch_ms) = self.last_switch_ms else {
+            // State unknown since startup
+            return 0;
+        };
+        (last_switch_ms + self.min_ms(limits, self.on) - now_ms).max(0)
+    }
+
+    fn min_ms(&self, limits: &RelayLimits, on: bool) -> i64 {
+        (if on { limits.min_on_sec } else { limits.min_off_sec }) as i64 * 1000
+    }
+
+    fn min_time_reason(&self) -> ProtectionReason {
+        if self.on { ProtectionReason::MinOnTime } else { ProtectionReason::MinOffTime }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pwm::{Control, PWMControl};
+    use chrono::{Local, TimeZone};
+
+    const LIMITS: RelayLimits = RelayLimits { min_on_sec: 300, min_off_sec: 600, max_switches_per_hour: 3 };
+
+    fn command(on: bool, delay_ms: u32) -> RelayCommand {
+        RelayCommand { on, delay_ms }
+    }
+
+    #[test]
+    fn min_on_and_off_time() {
+        let mut guard = RelayGuard::default();
+        assert_eq!(guard.apply(&LIMITS, command(true, 0), 0), (command(true, 0), None));
+
+        // Switching off after a minute waits for the rest of min_on_sec
+        assert_eq!(guard.apply(&LIMITS, command(false, 0), 60_000),
+            (command(false, 240_000), Some(ProtectionReason::MinOnTime)));
+        // The relay did that switch by itself at 300s
+        assert_eq!(guard.apply(&LIMITS, command(true, 0), 400_000),
+            (command(true, 500_000), Some(ProtectionReason::MinOffTime)));
+
+        // A short pulse while ON is dropped
+        let mut guard = RelayGuard::default();
+        guard.apply(&LIMITS, command(true, 0), 0);
+        assert_eq!(guard.apply(&LIMITS, command(true, 30_000), 10_000),
+            (command(true, 0), Some(ProtectionReason::MinOnTime)));
+        // A delayed OFF is pushed to the end of min_on_sec
+        assert_eq!(guard.apply(&LIMITS, command(false, 60_000), 20_000),
+            (command(false, 280_000), Some(ProtectionReason::MinOnTime)));
+    }
+
+    #[test]
+    fn switch_rate() {
+        let limits = RelayLimits { min_on_sec: 0, min_off_sec: 0, max_switches_per_hour: 3 };
+        let mut guard = RelayGuard::default();
+        for (i, on) in [true, false, true].into_iter().enumerate() {
+            assert_eq!(guard.apply(&limits, command(on, 0), i as i64 * 60_000), (command(on, 0), None));
+        }
+        // Switching OFF is never refused
+        assert_eq!(guard.apply(&limits, command(false, 0), 200_000), (command(false, 0), None));
+        assert_eq!(guard.apply(&limits, command(true, 0), 300_000),
+            (command(false, 0), Some(ProtectionReason::SwitchRate)));
+        // The first two switches are more than an hour old
+        assert_eq!(guard.apply(&limits, command(true, 0), 3_660_001), (command(true, 0), None));
+        // A pulse from ON keeps only its switch OFF
+        assert_eq!(guard.apply(&limits, command(true, 60_000), 3_700_000),
+            (command(false, 0), Some(ProtectionReason::SwitchRate)));
+    }
+
+    #[test]
+    fn short_pulse() {
+        let mut guard = RelayGuard::default();
+        guard.record(command(false, 0), 0);
+        // ON for 30s is stretched to min_on_sec
+        assert_eq!(guard.apply(&LIMITS, command(false, 30_000), 700_000),
+            (command(false, 300_000), Some(ProtectionReason::MinOnTime)));
+        // OFF for a minute is stretched to min_off_sec
+        assert_eq!(guard.apply(&LIMITS, command(true, 60_000), 1_000_000),
+            (command(true, 600_000), Some(ProtectionReason::MinOffTime)));
+    }
+
+    // An hour of PWM control 0.5°C below target, one reading per minute
+    fn run_pwm_hour(limits: &RelayLimits) -> (u32, u32, u32) {
+        let start = Local.timestamp_opt(1_700_000_000, 0).unwrap();
+        let mut pwm = PWMControl::new(0.0);
+        pwm.set_output(false, 0, Local.timestamp_opt(0, 0).unwrap());
+        let mut guard = RelayGuard::default();
+        let (mut refused, mut on_minutes, mut longest_on) = (0, 0, 0);
+        let mut on_run = 0;
+        for minute in 0..60 {
+            let time = start + chrono::Duration::minutes(minute);
+            let now_ms = time.timestamp_millis();
+            let decision = pwm.get_mode(19.5, 20.0, 20.0, time);
+            let (sent, reason) = guard.apply(limits, command(decision.on, decision.delay_ms), now_ms);
+            pwm.set_output(sent.on, sent.delay_ms, time);
+            refused += (reason == Some(ProtectionReason::SwitchRate)) as u32;
+            if guard.state(now_ms) {
+                on_minutes += 1;
+                on_run += 1;
+                longest_on = longest_on.max(on_run);
+            } else {
+                on_run = 0;
+            }
+        }
+        (refused, on_minutes, longest_on)
+    }
+
+    #[test]
+    fn pwm_through_guard() {
+        // The default limits keep the PWM cycle as it is
+        let (refused, on_minutes, _) = run_pwm_hour(&RelayProtectionConfig::default().limits("bedroom"));
+        assert_eq!(refused, 0);
+        assert!((20..50).contains(&on_minutes), "{on_minutes}");
+        // A low switch rate drops pulses, but never leaves the heater ON
+        let (refused, on_minutes, longest_on) =
+            run_pwm_hour(&RelayLimits { min_on_sec: 180, min_off_sec: 180, max_switches_per_hour: 6 });
+        assert!(refused > 0);
+        assert!(on_minutes < 30 && longest_on <= 10, "{on_minutes} {longest_on}");
+    }
+
+    #[test]
+    fn recorded_override_counts() {
+        let mut guard = RelayGuard::default();
+        guard.record(command(true, 0), 0);
+        assert_eq!(guard.apply(&LIMITS, command(false, 0), 100_000),
+            (command(false, 200_000), Some(ProtectionReason::MinOnTime)));
+    }
+
+    #[test]
+    fn pending_changes() {
+        let mut guard = RelayGuard::default();
+        guard.apply(&LIMITS, command(true, 0), 0);
+        assert!(!guard.changes(command(true, 0), 10_000));
+        assert!(guard.changes(command(false, 0), 10_000));
+
+        // Delayed OFF, the same switch recomputed a bit later is no change
+        guard.apply(&LIMITS, command(false, 600_000), 0);
+        assert_eq!(guard.pending(10_000), Some((false, 600_000)));
+        assert!(!guard.changes(command(false, 580_000), 30_000));
+        assert!(guard.changes(command(false, 300_000), 30_000));
+        assert!(guard.changes(command(true, 0), 30_000));
+
+        // The relay switched by itself
+        assert_eq!(guard.pending(600_000), None);
+        assert!(!guard.changes(command(false, 0), 700_000));
+    }
+}
@@ -0,0 +1,88 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// Frost protection stays on until the room is this much above the minimum
+const FROST_HYSTERESIS: f64 = 0.5;
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SafetyConfig {
+    // Heat below this temperature, even when disabled or switched off by hand
+    pub min_temp: f64,
+    // Never heat at or above this temperature, whatever the strategy wants
+    pub max_temp: f64,
+    // Per room name overrides
+    pub rooms: HashMap<String, RoomLimits>,
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct RoomLimits {
+    pub min_temp: Option<f64>,
+    pub max_temp: Option<f64>,
+}
+
+impl Default for SafetyConfig {
+    fn default() -> Self {
+        SafetyConfig { min_temp: 5.0, max_temp: 28.0, rooms: HashMap::new() }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SafetyIntervention {
+    // Forced ON below min_temp
+    FrostProtection,
+    // Forced OFF at or above max_temp
+    OverTemperature,
+}
+
+impl SafetyConfig {
+    // (min_temp, max_temp) for a room
+    pub fn limits(&self, room: &str) -> (f64, f64) {
+        let room = self.rooms.get(room).copied().unwrap_or_default();
+        (room.min_temp.unwrap_or(self.min_temp), room.max_temp.unwrap_or(self.max_temp))
+    }
+}
+
+// Intervention needed for the room temperature, `previous` is the one from the last report
+pub fn check(limits: (f64, f64), temp: f64, previous: Option<SafetyIntervention>) -> Option<SafetyIntervention> {
+    let (min_temp, max_temp) = limits;
+    if temp >= max_temp {
+        return Some(SafetyIntervention::OverTemperature);
+    }
+    let frost_release = if previous == Some(SafetyIntervention::FrostProtection) {
+        min_temp + FROST_HYSTERESIS
+    } else {
+        min_temp
+    };
+    if temp < frost_release {
+        return Some(SafetyIntervention::FrostProtection);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_and_hysteresis() {
+        let config: SafetyConfig = toml::from_str(r#"
+            min_temp = 7.0
+            [rooms]
+            kids_bedroom = { max_temp = 25.0 }
+        "#).unwrap();
+        assert_eq!(config.limits("bedroom"), (7.0, 28.0));
+        let limits = config.limits("kids_bedroom");
+        assert_eq!(limits, (7.0, 25.0));
+
+        assert_eq!(check(limits, 20.0, None), None);
+        assert_eq!(check(limits, 25.0, None), Some(SafetyIntervention::OverTemperature));
+        assert_eq!(check(limits, 6.9, None), Some(SafetyIntervention::FrostProtection));
+        // Keeps heating until min_temp + hysteresis
+        assert_eq!(check(limits, 7.2, Some(SafetyIntervention::FrostProtection)), Some(SafetyIntervention::FrostProtection));
+        assert_eq!(check(limits, 7.2, None), None);
+        assert_eq!(check(limits, 7.5, Some(SafetyIntervention::FrostProtection)), None);
+    }
+}
@@ -0,0 +1,134 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::button::RoomMode;
+use crate::web::TemperaturePoint;
+
+// What happens on SIGTERM / SIGINT before the server exits
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShutdownConfig {
+    // Relay state sent to every room, unless overridden in `rooms`
+    pub relay_state: SafeState,
+    // Boiler relay and heat_on subscribers of [heat_demand]
+    pub boiler_state: SafeState,
+    pub rooms: HashMap<String, SafeState>,
+    // History, disabled rooms, button modes and learned offsets, restored at startup.
+    // Empty disables saving.
+    pub state_file: PathBuf,
+    // Longest wait for the control task to finish, then for open web connections (e.g. event streams)
+    pub timeout_sec: u64,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        ShutdownConfig {
+            relay_state: SafeState::Off,
+            boiler_state: SafeState::Off,
+            rooms: HashMap::new(),
+            state_file: PathBuf::from("/var/lib/temperature/state.json"),
+            timeout_sec: 5,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SafeState {
+    Off,
+    On,
+    // Leave the relay as it is, pending delayed commands still apply
+    Keep,
+}
+
+impl SafeState {
+    // Relay output to send, None for Keep
+    pub fn output(self) -> Option<bool> {
+        match self {
+            SafeState::Off => Some(false),
+            SafeState::On => Some(true),
+            SafeState::Keep => None,
+        }
+    }
+}
+
+impl ShutdownConfig {
+    pub fn relay_state(&self, room: &str) -> SafeState {
+        self.rooms.get(room).copied().unwrap_or(self.relay_state)
+    }
+}
+
+// State kept across restarts, keyed by room name
+#[derive(Serialize, Deserialize, Default)]
+pub struct SavedState {
+    pub saved_at: i64,
+    pub rooms: HashMap<String, SavedRoom>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct SavedRoom {
+    pub temperature_history: Vec<TemperaturePoint>,
+    pub disabled_until: Option<i64>,
+    pub mode: Option<RoomMode>,
+    // Learned offset of the room's control strategy
+    pub offset: Option<f64>,
+}
+
+impl SavedState {
+    // None when there is no saved state yet
+    pub fn load(path: &Path) -> Result<Option<SavedState>> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        serde_json::from_str(&text)
+            .map(Some)
+            .with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    // Written to a temporary file first, a crash never leaves a partial state
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let tmp_path = path.with_extension("tmp");
+        std::fs::write(&tmp_path, serde_json::to_vec(self)?)
+            .with_context(|| format!("Failed to write {}", tmp_path.display()))?;
+        std::fs::rename(&tmp_path, path)
+            .with_context(|| format!("Failed to rename {} to {}", tmp_path.display(), path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_and_load() {
+        let config: ShutdownConfig = toml::from_str(r#"
+            relay_state = "keep"
+            rooms = { bedroom = "off" }
+        "#).unwrap();
+        assert_eq!(config.relay_state("bedroom").output(), Some(false));
+        assert_eq!(config.relay_state("irina").output(), None);
+        assert_eq!(config.boiler_state, SafeState::Off);
+
+        let path = std::env::temp_dir().join(format!("temperature-state-{}.json", std::process::id()));
+        assert!(SavedState::load(&path).unwrap().is_none());
+        let mut state = SavedState { saved_at: 100, ..Default::default() };
+        state.rooms.insert("bedroom".to_string(), SavedRoom {
+            temperature_history: vec![TemperaturePoint { timestamp: 90, temperature: 20.5, ..Default::default() }],
+            disabled_until: Some(200),
+            mode: Some(RoomMode::Away { target: 16.0 }),
+            offset: Some(-0.3),
+        });
+        state.save(&path).unwrap();
+        let loaded = SavedState::load(&path).unwrap().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let room = &loaded.rooms["bedroom"];
+        assert_eq!(room.temperature_history, state.rooms["bedroom"].temperature_history);
+        assert_eq!(room.mode, Some(RoomMode::Away { target: 16.0 }));
+        assert_eq!(room.offset, Some(-0.3));
+    }
+}
@@ -0,0 +1,132 @@
+use axum::Router;
+use std::path::PathBuf;
+use tower_http::services::{ServeDir, ServeFile};
+
+// Router serving the React UI. `static_dir` (--static-dir) wins over the embedded
+// copy so the UI can be rebuilt without rebuilding the server.
+pub fn static_router<S: Clone + Send + Sync + 'static>(static_dir: Option<PathBuf>) -> Router<S> {
+    match static_dir {
+        Some(dir) => dir_router(dir),
+        None => default_router(),
+    }
+}
+
+fn dir_router<S: Clone + Send + Sync + 'static>(dir: PathBuf) -> Router<S> {
+    tracing::info!("React app is served from: {}", dir.display());
+    // Unknown paths get index.html for SPA routing
+    let index = ServeFile::new(dir.join("index.html"));
+    Router::new().fallback_service(ServeDir::new(dir).fallback(index))
+}
+
+#[cfg(not(feature = "embed-ui"))]
+fn default_router<S: Clone + Send + Sync + 'static>() -> Router<S> {
+    // Only works from a source checkout, build with --features embed-ui for deployment
+    let react_dist_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .parent().unwrap().parent().unwrap() // Navigate from apps/server/Cargo.toml to repo root
+        .join("temperature-react-ui/dist");
+    dir_router(react_dist_path)
+}
+
+#[cfg(feature = "embed-ui")]
+fn default_router<S: Clone + Send + Sync + 'static>() -> Router<S> {
+    tracing::info!("React app is served from the embedded build");
+    Router::new().fallback(axum::routing::get(embedded::serve))
+}
+
+#[cfg(feature = "embed-ui")]
+mod embedded {
+    use axum::http::{header, HeaderMap, StatusCode, Uri};
+    use axum::response::{IntoResponse, Response};
+
+    #[derive(rust_embed::RustEmbed)]
+    #[folder = "../../temperature-react-ui/dist"]
+    #[allow_missing = true]
+    struct Ui;
+
+    pub async fn serve(uri: Uri, headers: HeaderMap) -> Response {
+        let path = uri.path().trim_start_matches('/');
+        let (path, file) = match Ui::get(path) {
+            Some(file) => (path, file),
+            // Missing hashed assets are real 404s, everything else is a SPA route
+            None if path.starts_with("assets/") => return StatusCode::NOT_FOUND.into_response(),
+            None => match Ui::get("index.html") {
+                Some(file) => ("index.html", file),
+                None => return (StatusCode::INTERNAL_SERVER_ERROR, "UI was not built before embedding").into_response(),
+            },
+        };
+
+        let hash: String = file.metadata.sha256_hash().iter().map(|b| format!("{:02x}", b)).collect();
+        let etag = format!("\"{}\"", hash);
+        // Vite puts content hashes into names under assets/, the rest must be revalidated
+        let cache_control = if path.starts_with("assets/") {
+            "public, max-age=31536000, immutable"
+        } else {
+            "no-cache"
+        };
+
+        let not_modified = headers.get(header::IF_NONE_MATCH)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.split(',').any(|tag| tag.trim() == etag || tag.trim() == "*"));
+        if not_modified {
+            return (StatusCode::NOT_MODIFIED, [(header::ETAG, etag), (header::CACHE_CONTROL, cache_control.to_string())]).into_response();
+        }
+
+        (
+            [
+                (header::CONTENT_TYPE, file.metadata.mimetype().to_string()),
+                (header::ETAG, etag),
+                (header::CACHE_CONTROL, cache_control.to_string()),
+            ],
+            file.data,
+        ).into_response()
+    }
+}
+
+#[cfg(all(test, feature = "embed-ui"))]
+mod tests {
+    use super::embedded::serve;
+    use axum::body::to_bytes;
+    use axum::http::{header, HeaderMap, HeaderValue, StatusCode, Uri};
+    use axum::response::Response;
+
+    async fn get(path: &str, headers: HeaderMap) -> Response {
+        serve(Uri::try_from(path).unwrap(), headers).await
+    }
+
+    async fn body(response: Response) -> Vec<u8> {
+        to_bytes(response.into_body(), usize::MAX).await.unwrap().to_vec()
+    }
+
+    #[tokio::test]
+    async fn serve_embedded() {
+        // Missing hashed assets are not answered with index.html
+        assert_eq!(get("/assets/missing-0000.js", HeaderMap::new()).await.status(), StatusCode::NOT_FOUND);
+
+        let index = get("/index.html", HeaderMap::new()).await;
+        if index.status() == StatusCode::INTERNAL_SERVER_ERROR {
+            // Built without the UI
+            assert_eq!(get("/rooms", HeaderMap::new()).await.status(), StatusCode::INTERNAL_SERVER_ERROR);
+            return;
+        }
+        assert_eq!(index.status(), StatusCode::OK);
+        assert_eq!(index.headers()[header::CACHE_CONTROL], "no-cache");
+        let etag = index.headers()[header::ETAG].clone();
+        let index = body(index).await;
+
+        // SPA routes get index.html
+        let route = get("/rooms/bedroom", HeaderMap::new()).await;
+        assert_eq!(route.status(), StatusCode::OK);
+        assert_eq!(route.headers()[header::ETAG], etag);
+        assert_eq!(body(route).await, index);
+
+        // Revalidation with the ETag
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_NONE_MATCH, etag.clone());
+        let cached = get("/index.html", headers).await;
+        assert_eq!(cached.status(), StatusCode::NOT_MODIFIED);
+        assert!(body(cached).await.is_empty());
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_NONE_MATCH, HeaderValue::from_static("\"other\""));
+        assert_eq!(get("/index.html", headers).await.status(), StatusCode::OK);
+    }
+}
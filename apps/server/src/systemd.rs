@@ -0,0 +1,142 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::os::fd::{FromRawFd, OwnedFd};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::Duration;
+use tracing::{debug, warn};
+
+// Readiness, watchdog and status for Type=notify services, see sd_notify(3).
+// Without NOTIFY_SOCKET (not started by systemd) nothing is sent.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SystemdConfig {
+    // The control task handling one message or command longer than this counts as wedged,
+    // watchdog pings stop and systemd restarts the service
+    pub stall_sec: i64,
+    // STATUS= line with room temperatures is updated this often
+    pub status_sec: u64,
+}
+
+impl Default for SystemdConfig {
+    fn default() -> Self {
+        SystemdConfig { stall_sec: 60, status_sec: 30 }
+    }
+}
+
+// Sends a state like "READY=1", returns false when not run by systemd
+pub fn notify(state: &str) -> bool {
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return false;
+    };
+    let path = path.to_string_lossy();
+    // A leading '@' is a socket in the abstract namespace
+    let addr = match path.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name),
+        None => SocketAddr::from_pathname(path.as_ref()),
+    };
+    let result = addr.and_then(|addr| UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &addr));
+    if let Err(e) = result {
+        warn!(socket = %path, error = %e, "failed to notify systemd");
+        return false;
+    }
+    true
+}
+
+// Interval of watchdog pings, half of WatchdogSec= of the unit
+pub fn watchdog_interval() -> Option<Duration> {
+    if !for_this_process("WATCHDOG_PID") {
+        return None;
+    }
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    Some(Duration::from_micros(usec / 2)).filter(|interval| !interval.is_zero())
+}
+
+// Variables meant for this process, a missing PID counts as ours
+fn for_this_process(pid_var: &str) -> bool {
+    std::env::var(pid_var).map_or(true, |pid| pid == std::process::id().to_string())
+}
+
+// Sockets passed by socket activation by FileDescriptorName= ("udp", "http", "https"),
+// see sd_listen_fds(3). Taken once, the variables are removed for child processes.
+pub fn listen_fds() -> HashMap<String, OwnedFd> {
+    const SD_LISTEN_FDS_START: i32 = 3;
+    let mut fds = HashMap::new();
+    let Some(count) = std::env::var("LISTEN_FDS").ok().and_then(|count| count.parse::<i32>().ok()) else {
+        return fds;
+    };
+    if std::env::var("LISTEN_PID").is_err() || !for_this_process("LISTEN_PID") {
+        return fds;
+    }
+    let names = std::env::var("LISTEN_FDNAMES").unwrap_or_default();
+    let mut names = names.split(':');
+    for fd in SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + count {
+        let name = names.next().unwrap_or("unknown").to_string();
+        debug!(fd, name, "socket passed by systemd");
+        // systemd hands these descriptors over to this process, nothing else owns them
+        fds.insert(name, unsafe { OwnedFd::from_raw_fd(fd) });
+    }
+    for var in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        std::env::remove_var(var);
+    }
+    fds
+}
+
+// Progress of the control task: the event it handles and its periodic checks,
+// timestamps are unix seconds
+#[derive(Default)]
+pub struct Liveness {
+    // Start of the event being handled, 0 while waiting for one
+    busy_since: AtomicI64,
+    checked_at: AtomicI64,
+}
+
+impl Liveness {
+    pub fn event_started(&self, now: i64) {
+        self.busy_since.store(now, Ordering::Relaxed);
+    }
+
+    pub fn event_done(&self) {
+        self.busy_since.store(0, Ordering::Relaxed);
+    }
+
+    pub fn checked(&self, now: i64) {
+        self.checked_at.store(now, Ordering::Relaxed);
+    }
+
+    // None while both make progress, otherwise what is stuck
+    pub fn stalled(&self, now: i64, stall_sec: i64, check_interval_sec: i64) -> Option<&'static str> {
+        let busy_since = self.busy_since.load(Ordering::Relaxed);
+        if busy_since != 0 && now - busy_since > stall_sec {
+            return Some("control task");
+        }
+        // Two missed checks, a check may wait behind an event as long as stall_sec
+        if now - self.checked_at.load(Ordering::Relaxed) > 2 * check_interval_sec.max(stall_sec) {
+            return Some("periodic checks");
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stalls() {
+        let liveness = Liveness::default();
+        liveness.checked(1000);
+        // Waiting for messages is fine
+        assert_eq!(liveness.stalled(1100, 60, 10), None);
+        liveness.checked(1100);
+        liveness.event_started(1100);
+        assert_eq!(liveness.stalled(1150, 60, 10), None);
+        assert_eq!(liveness.stalled(1161, 60, 10), Some("control task"));
+        liveness.event_done();
+        // Checks wait behind an event at most stall_sec, they run every 10 s
+        assert_eq!(liveness.stalled(1221, 60, 10), Some("periodic checks"));
+        liveness.checked(1220);
+        assert_eq!(liveness.stalled(1221, 60, 10), None);
+    }
+}
@@ -1,33 +1,153 @@
 use axum::{
     routing::{get, post},
     Router,
-    response::{Html, IntoResponse},
-    extract::{State, Json, Query},
-    http::{StatusCode, Uri}, // Added Uri
+    response::{IntoResponse, Redirect, Response, sse::{Event, KeepAlive, Sse}},
+    extract::{State, Json, Query, FromRequestParts, ConnectInfo},
+    http::{StatusCode, Uri, HeaderMap, header, request::Parts}, // Added Uri
 };
 use std::sync::Arc;
-use tower_http::services::ServeDir;
 use tower_http::compression::CompressionLayer;
 use tokio::sync::RwLock; // Keep tokio RwLock
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
+use futures::stream::{self, Stream};
 use serde::{Serialize, Deserialize};
-use temperature_protocol::relay::set_relay;
 use chrono::Local;
-use std::path::PathBuf; // Added PathBuf
-use tokio::fs; // Added tokio::fs for reading index.html
+use std::collections::{BTreeMap, HashMap};
+use std::os::fd::OwnedFd;
+use std::path::PathBuf;
+use crate::auth::{Auth, Role, User};
+use crate::button::{ButtonAction, RoomMode};
+use crate::audit::{AuditKind, AuditLog, AuditQuery};
+use crate::calibration::Calibrator;
+use crate::demand::HeatDemandState;
+use crate::devices::{DeviceInventory, DeviceKind, DeviceRegistry};
+use crate::fault::HeaterFault;
+use crate::relay_guard::ProtectionReason;
+use crate::static_files::static_router;
+use anyhow::Context;
+use axum_server::tls_rustls::RustlsConfig;
+use std::net::SocketAddr;
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{error, info};
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebConfig {
+    // Plain HTTP listener, redirects to HTTPS when `tls.redirect_http` is set
+    pub bind: SocketAddr,
+    pub tls: Option<TlsConfig>,
+    // Serve the UI from this directory instead of the built-in location, also --static-dir
+    pub static_dir: Option<PathBuf>,
+}
+
+impl Default for WebConfig {
+    fn default() -> Self {
+        WebConfig { bind: SocketAddr::from(([0, 0, 0, 0], 8080)), tls: None, static_dir: None }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    #[serde(default = "default_tls_bind")]
+    pub bind: SocketAddr,
+    // PEM certificate chain and private key, reloaded on SIGHUP
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    #[serde(default = "default_true")]
+    pub redirect_http: bool,
+}
+
+fn default_tls_bind() -> SocketAddr {
+    SocketAddr::from(([0, 0, 0, 0], 8443))
+}
+
+fn default_true() -> bool {
+    true
+}
 
 // Shared state between temperature server and web server
 #[derive(Clone)]
 pub struct WebState {
     pub server_state: Arc<RwLock<ServerState>>,
+    pub events: broadcast::Sender<StatusEvent>,
+    pub auth: Arc<Auth>,
+    pub audit: Arc<AuditLog>,
+    pub calibrator: Arc<Calibrator>,
+    pub devices: Arc<DeviceRegistry>,
+    pub inventory: Arc<DeviceInventory>,
+    // Relay and disable requests, executed by the control task
+    pub commands: mpsc::Sender<WebCommand>,
+}
+
+// Requests of the web layer that change the control state, answered on `reply`
+pub enum WebCommand {
+    SetRelay { room: String, on: bool, reply: oneshot::Sender<Result<(), String>> },
+    // Heating off for two hours, or back on
+    Disable { room: String, disable: bool, reply: oneshot::Sender<Result<(), String>> },
+}
+
+// User making the request, None if not logged in
+pub struct CurrentUser(pub Option<User>);
+
+impl FromRequestParts<WebState> for CurrentUser {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &WebState) -> Result<Self, Self::Rejection> {
+        Ok(CurrentUser(state.auth.authenticate(&parts.headers)))
+    }
+}
+
+fn api_error(status: StatusCode, error: &str) -> Response {
+    (status, axum::Json(serde_json::json!({ "success": false, "error": error }))).into_response()
+}
+
+// Mutating endpoints require a user who may control the room
+fn authorize(user: &Option<User>, room: &str) -> Result<(), (StatusCode, &'static str)> {
+    match user {
+        None => Err((StatusCode::UNAUTHORIZED, "Login required")),
+        Some(user) if !user.can_control(room) => Err((StatusCode::FORBIDDEN, "Not allowed to control this room")),
+        Some(_) => Ok(()),
+    }
+}
+
+// Sensors are calibrated by users who control their room, sensors without a room only by admins
+fn authorize_sensor(devices: &DeviceRegistry, user: &Option<User>, sensor: &str) -> Result<(), (StatusCode, &'static str)> {
+    let room = devices.sensor_id(sensor).and_then(|id| devices.room_of(DeviceKind::Sensor, id));
+    match (user, room) {
+        (Some(_), Some(room)) => authorize(user, room),
+        (Some(user), None) if matches!(user.role, Role::Admin) => Ok(()),
+        (Some(_), None) => Err((StatusCode::FORBIDDEN, "Not allowed to calibrate this sensor")),
+        (None, _) => Err((StatusCode::UNAUTHORIZED, "Login required")),
+    }
 }
 
 #[derive(Default, Clone, Serialize)]
 pub struct ServerState {
-    pub bedroom: RoomState,
-    pub kids_bedroom: RoomState,
+    // Rooms with devices in the registry, by room name
+    pub rooms: BTreeMap<String, RoomState>,
+    // Aggregated demand, None when [heat_demand] is not enabled
+    pub heat_demand: Option<HeatDemandState>,
 }
 
-#[derive(Default, Clone, Serialize)]
+impl ServerState {
+    pub fn new<'a>(rooms: impl Iterator<Item = &'a str>) -> ServerState {
+        ServerState {
+            rooms: rooms.map(|room| (room.to_string(), RoomState::default())).collect(),
+            heat_demand: None,
+        }
+    }
+
+    pub fn room(&self, room: &str) -> Option<&RoomState> {
+        self.rooms.get(room)
+    }
+
+    pub fn room_mut(&mut self, room: &str) -> Option<&mut RoomState> {
+        self.rooms.get_mut(room)
+    }
+}
+
+#[derive(Default, Clone, Serialize, PartialEq)]
 pub struct RoomState {
     pub sensor_available: bool,
     pub current_temp: f64,
@@ -36,15 +156,87 @@ pub struct RoomState {
     pub relay_state: bool,
     pub temperature_history: Vec<TemperaturePoint>,
     pub disabled_until: Option<i64>, // Timestamp when disabled state expires
+    pub failsafe: bool, // Sensor is silent, relay is driven by the failsafe
+    pub window_open_until: Option<i64>, // Heating paused after an open window was detected
+    pub faults: Vec<HeaterFault>, // Heater or relay faults currently detected
+    pub rejected_readings: u64, // Implausible sensor readings dropped since startup
+    pub sensors: Vec<SensorState>, // Readings of every sensor of the room
+    pub mode: Option<RoomMode>, // Forced heat, boost or away set with the sensor button
+    pub pending_switch: Option<PendingSwitch>, // Delayed switch the relay was commanded to do
 }
 
-#[derive(Default, Clone, Serialize)]
+#[derive(Clone, Copy, Debug, Serialize, PartialEq)]
+pub struct PendingSwitch {
+    pub on: bool,
+    pub at: i64, // Timestamp of the switch
+}
+
+// One of the room's sensors, the room temperature is fused from the fresh ones
+#[derive(Default, Clone, Serialize, PartialEq)]
+pub struct SensorState {
+    pub device_id: u32,
+    pub temperature: Option<f64>,
+    pub humidity: Option<f64>,
+    pub timestamp: Option<i64>,
+    pub stale: bool,
+    pub rejected_readings: u64,
+}
+
+#[derive(Default, Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct TemperaturePoint {
     pub timestamp: i64,
     pub temperature: f64,
     pub target: f64,
     pub heater_on: bool,
     pub is_disabled: bool,
+    // Relay protection changed the strategy's decision
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub protection: Option<ProtectionReason>,
+    // Button mode active for the point
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<ButtonAction>,
+}
+
+impl RoomState {
+    // Copy of the room state without the history, used for live updates
+    pub fn summary(&self) -> RoomState {
+        RoomState {
+            sensor_available: self.sensor_available,
+            current_temp: self.current_temp,
+            target_temp: self.target_temp,
+            relay_available: self.relay_available,
+            relay_state: self.relay_state,
+            temperature_history: Vec::new(),
+            disabled_until: self.disabled_until,
+            failsafe: self.failsafe,
+            window_open_until: self.window_open_until,
+            faults: self.faults.clone(),
+            rejected_readings: self.rejected_readings,
+            sensors: self.sensors.clone(),
+            mode: self.mode,
+            pending_switch: self.pending_switch,
+        }
+    }
+}
+
+// Live updates pushed to /api/events subscribers
+#[derive(Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StatusEvent {
+    // Room state changed, temperature_history is always empty
+    Room { room: String, state: RoomState },
+    // New point appended to the room's temperature history
+    Point { room: String, point: TemperaturePoint },
+    // Aggregated heat demand or boiler output changed
+    HeatDemand { state: HeatDemandState },
+}
+
+// Small buffer is enough, slow clients get a "resync" event and refetch /api/status
+pub const EVENTS_CAPACITY: usize = 256;
+
+pub fn publish(events: &broadcast::Sender<StatusEvent>, event: StatusEvent) {
+    // Error only means there are no subscribers right now
+    let _ = events.send(event);
 }
 
 #[derive(Deserialize)]
@@ -58,69 +250,168 @@ pub struct StatusQuery {
     last_update: Option<i64>,
 }
 
+#[derive(Deserialize)]
+pub struct LoginRequest {
+    name: String,
+    password: String,
+}
+
 #[derive(Deserialize)]
 pub struct DisableHeaterRequest {
     room: String,
     disable: bool, // true to disable, false to restore
 }
 
+#[derive(Deserialize)]
+pub struct CalibrationRequest {
+    sensor: String, // Sensor name, see [calibration.sensors]
+}
 
-pub async fn create_web_server(server_state: Arc<RwLock<ServerState>>) {
-    let app_state = WebState { server_state };
+#[derive(Deserialize)]
+pub struct ReferenceReadingRequest {
+    sensor: String,
+    temperature: f64,
+    humidity: Option<f64>,
+}
 
-    // Path to the React app's dist directory - adjust if server runs from different location
-    let react_dist_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-        .parent().unwrap().parent().unwrap() // Navigate from apps/server/Cargo.toml to repo root
-        .join("temperature-react-ui/dist");
+// Sockets of the web server, bound before the server reports it is ready
+pub struct WebListeners {
+    http: tokio::net::TcpListener,
+    https: Option<std::net::TcpListener>,
+}
 
-    let assets_path = react_dist_path.join("assets");
+// Binds the configured addresses, unless systemd passed sockets named "http" / "https"
+pub async fn bind(config: &WebConfig, activated: &mut HashMap<String, OwnedFd>) -> anyhow::Result<WebListeners> {
+    let http = match activated.remove("http") {
+        Some(fd) => {
+            let listener = std::net::TcpListener::from(fd);
+            listener.set_nonblocking(true)?;
+            tokio::net::TcpListener::from_std(listener)?
+        }
+        None => tokio::net::TcpListener::bind(config.bind).await
+            .with_context(|| format!("Failed to bind {}", config.bind))?,
+    };
+    let https = match (&config.tls, activated.remove("https")) {
+        (None, _) => None,
+        (Some(_), Some(fd)) => Some(std::net::TcpListener::from(fd)),
+        (Some(tls), None) => Some(std::net::TcpListener::bind(tls.bind)
+            .with_context(|| format!("Failed to bind {}", tls.bind))?),
+    };
+    if let Some(listener) = &https {
+        // Required by tokio, which axum-server converts it to
+        listener.set_nonblocking(true)?;
+    }
+    Ok(WebListeners { http, https })
+}
 
-    let spa_router = Router::new()
-        .nest_service("/assets", ServeDir::new(assets_path.clone())) // Serve static assets (JS, CSS)
-        .nest_service("/favicon.ico", tower_http::services::ServeFile::new(react_dist_path.join("favicon.ico")))
-        .nest_service("/manifest.json", tower_http::services::ServeFile::new(react_dist_path.join("manifest.json")))
-        // Add other specific public files if needed (e.g., favicons, svgs)
-        // These were moved to temperature-react-ui/public and should be in dist after build
-        .nest_service("/favicon.png", tower_http::services::ServeFile::new(react_dist_path.join("favicon.png")))
-        .nest_service("/favicon-192.png", tower_http::services::ServeFile::new(react_dist_path.join("favicon-192.png")))
-        .nest_service("/favicon-512.png", tower_http::services::ServeFile::new(react_dist_path.join("favicon-512.png")))
-        .nest_service("/power.svg", tower_http::services::ServeFile::new(react_dist_path.join("power.svg")))
-        .nest_service("/thermometer.svg", tower_http::services::ServeFile::new(react_dist_path.join("thermometer.svg")))
-        .nest_service("/vite.svg", tower_http::services::ServeFile::new(react_dist_path.join("vite.svg"))) // Vite's default icon
-        .fallback(get(serve_react_app_index)); // Fallback to serving index.html for SPA routing
+// Returns after `shutdown` turns true and open connections are closed
+pub async fn create_web_server(config: WebConfig, app_state: WebState, listeners: WebListeners,
+    shutdown: watch::Receiver<bool>) -> anyhow::Result<()> {
 
+    let spa_router = static_router(config.static_dir);
 
     let app = Router::new()
         // API routes (ensure they are matched before SPA fallback)
         .route("/api/status", get(get_status))
+        .route("/api/events", get(stream_events))
+        .route("/api/events/log", get(get_audit_log))
         .route("/api/relay", post(control_relay))
         .route("/api/disable", post(disable_heater))
+        .route("/api/devices", get(get_devices))
+        .route("/api/calibration", get(get_calibration))
+        .route("/api/calibration/start", post(start_calibration))
+        .route("/api/calibration/stop", post(stop_calibration))
+        .route("/api/calibration/reference", post(calibration_reference))
+        .route("/api/login", post(login))
+        .route("/api/logout", post(logout))
+        .route("/api/me", get(current_user))
         // Mount the SPA router (serving static files and index.html)
         // IMPORTANT: This should generally be the last thing if it has a broad fallback
-        .merge(spa_router) 
+        .merge(spa_router)
         .layer(CompressionLayer::new())
         .with_state(app_state);
 
-    println!("Starting web server on http://localhost:8080");
-    println!("React app should be served from: {}", react_dist_path.display());
-    println!("Assets should be served from: {}", assets_path.display());
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:8080").await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    let listener = listeners.http;
+    let (Some(tls), Some(https_listener)) = (config.tls, listeners.https) else {
+        info!("Starting web server on http://{}", listener.local_addr()?);
+        axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+            .with_graceful_shutdown(shutdown_requested(shutdown))
+            .await?;
+        return Ok(());
+    };
+
+    // Certificates are parsed by rustls, which needs a process-wide crypto provider
+    let _ = rustls::crypto::ring::default_provider().install_default();
+    let rustls_config = RustlsConfig::from_pem_file(&tls.cert, &tls.key).await
+        .with_context(|| format!("Failed to load certificate {} / {}", tls.cert.display(), tls.key.display()))?;
+    tokio::spawn(reload_certificate_on_sighup(rustls_config.clone(), tls.clone()));
+
+    // Plain HTTP listener either redirects to HTTPS or serves the same app
+    let http_app = if tls.redirect_http {
+        let https_port = tls.bind.port();
+        Router::new().fallback(move |headers: HeaderMap, uri: Uri| redirect_to_https(headers, uri, https_port))
+    } else {
+        app.clone()
+    };
+    let http_app = http_app.into_make_service_with_connect_info::<SocketAddr>();
+    info!("Starting {} on http://{}", if tls.redirect_http { "HTTPS redirect" } else { "web server" }, listener.local_addr()?);
+    let http_shutdown = shutdown.clone();
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, http_app).with_graceful_shutdown(shutdown_requested(http_shutdown)).await {
+            error!("HTTP listener failed: {}", e);
+        }
+    });
+
+    let handle = axum_server::Handle::new();
+    let https_handle = handle.clone();
+    tokio::spawn(async move {
+        shutdown_requested(shutdown).await;
+        https_handle.graceful_shutdown(None);
+    });
+    info!("Starting web server on https://{}", https_listener.local_addr()?);
+    axum_server::from_tcp_rustls(https_listener, rustls_config)?
+        .handle(handle)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .await?;
+    Ok(())
 }
 
-// Serves the index.html for the React SPA
-async fn serve_react_app_index(uri: Uri) -> impl IntoResponse {
-    println!("Fallback route hit for URI: {}", uri); // Log which URI is hitting fallback
-    let react_dist_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-        .parent().unwrap().parent().unwrap()
-        .join("temperature-react-ui/dist");
-    let index_html_path = react_dist_path.join("index.html");
+async fn shutdown_requested(mut shutdown: watch::Receiver<bool>) {
+    // An error means main is gone, stop as well
+    let _ = shutdown.wait_for(|stopping| *stopping).await;
+}
 
-    match fs::read_to_string(index_html_path).await {
-        Ok(content) => Html(content).into_response(),
+async fn redirect_to_https(headers: HeaderMap, uri: Uri, https_port: u16) -> Response {
+    let Some(host) = headers.get(header::HOST).and_then(|h| h.to_str().ok()) else {
+        return (StatusCode::BAD_REQUEST, "Missing Host header").into_response();
+    };
+    // Drop the HTTP port, keeping IPv6 literals like [::1] intact
+    let host = match host.rsplit_once(':') {
+        Some((name, port)) if !port.contains(']') => name,
+        _ => host,
+    };
+    let path = uri.path_and_query().map_or("/", |p| p.as_str());
+    let location = if https_port == 443 {
+        format!("https://{}{}", host, path)
+    } else {
+        format!("https://{}:{}{}", host, https_port, path)
+    };
+    Redirect::permanent(&location).into_response()
+}
+
+// Certificates renewed by certbot & co are picked up with `kill -HUP`
+async fn reload_certificate_on_sighup(rustls_config: RustlsConfig, tls: TlsConfig) {
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
         Err(e) => {
-            eprintln!("Error reading React index.html: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to load React app: {}", e)).into_response()
+            error!("Failed to install SIGHUP handler: {}", e);
+            return;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        match rustls_config.reload_from_pem_file(&tls.cert, &tls.key).await {
+            Ok(()) => info!("Reloaded certificate {}", tls.cert.display()),
+            Err(e) => error!("Failed to reload certificate {}: {}", tls.cert.display(), e),
         }
     }
 }
@@ -133,70 +424,201 @@ async fn get_status(
     let mut response_state = (*server_state).clone();
 
     if let Some(last_update) = query.last_update {
-        response_state.bedroom.temperature_history = server_state.bedroom.temperature_history
-            .iter()
-            .filter(|point| point.timestamp > last_update)
-            .cloned()
-            .collect();
-        response_state.kids_bedroom.temperature_history = server_state.kids_bedroom.temperature_history
-            .iter()
-            .filter(|point| point.timestamp > last_update)
-            .cloned()
-            .collect();
+        for room_state in response_state.rooms.values_mut() {
+            room_state.temperature_history.retain(|point| point.timestamp > last_update);
+        }
     }
     axum::Json(response_state)
 }
 
-async fn control_relay(
+// Server-Sent Events stream of room state changes and new history points.
+// Clients are expected to load /api/status once and then apply the events.
+async fn stream_events(
     State(state): State<WebState>,
-    Json(request): Json<RelayControlRequest>,
+) -> Sse<impl Stream<Item = Result<Event, axum::Error>>> {
+    let receiver = state.events.subscribe();
+    let events = stream::unfold(receiver, |mut receiver| async move {
+        let event = match receiver.recv().await {
+            Ok(event) => Event::default().json_data(&event),
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                Ok(Event::default().event("resync").data(skipped.to_string()))
+            }
+            Err(broadcast::error::RecvError::Closed) => return None,
+        };
+        Some((event, receiver))
+    });
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+async fn login(
+    State(state): State<WebState>,
+    Json(request): Json<LoginRequest>,
+) -> Response {
+    match state.auth.login(&request.name, &request.password) {
+        Some(session_id) => (
+            [(header::SET_COOKIE, state.auth.session_cookie(&session_id))],
+            axum::Json(serde_json::json!({ "success": true })),
+        ).into_response(),
+        None => api_error(StatusCode::UNAUTHORIZED, "Invalid name or password"),
+    }
+}
+
+async fn logout(
+    State(state): State<WebState>,
+    headers: HeaderMap,
+) -> Response {
+    state.auth.logout(&headers);
+    (
+        [(header::SET_COOKIE, state.auth.clear_cookie())],
+        axum::Json(serde_json::json!({ "success": true })),
+    ).into_response()
+}
+
+async fn current_user(
+    State(state): State<WebState>,
+    CurrentUser(user): CurrentUser,
 ) -> axum::Json<serde_json::Value> {
-    let relay_hostname = match request.room.as_str() {
-        "bedroom" => "esp8266-relay0.local",
-        "kids_bedroom" => "esp8266-relay2.local",
-        _ => return axum::Json(serde_json::json!({ "success": false, "error": "Invalid room" }))
-    };
+    axum::Json(serde_json::json!({ "auth_enabled": state.auth.enabled(), "user": user }))
+}
 
-    match set_relay(relay_hostname, request.state, 0) {
-        Ok(_) => {
-            let mut server_state = state.server_state.write().await;
-            match request.room.as_str() {
-                "bedroom" => server_state.bedroom.relay_state = request.state,
-                "kids_bedroom" => server_state.kids_bedroom.relay_state = request.state,
-                _ => {}
-            }
-            axum::Json(serde_json::json!({ "success": true }))
-        }
-        Err(e) => axum::Json(serde_json::json!({ "success": false, "error": e.to_string() }))
+// Audit trail of web actions, control decisions, relay commands and device events
+async fn get_audit_log(
+    State(state): State<WebState>,
+    CurrentUser(user): CurrentUser,
+    Query(query): Query<AuditQuery>,
+) -> Response {
+    if user.is_none() {
+        return api_error(StatusCode::UNAUTHORIZED, "Login required");
     }
+    axum::Json(state.audit.query(&query)).into_response()
+}
+
+fn record_web_action(state: &WebState, user: &Option<User>, source: SocketAddr, action: &str, room: &str) {
+    state.audit.record(AuditKind::WebAction {
+        user: user.as_ref().map_or("-", |u| u.name.as_str()).to_string(),
+        source: source.ip().to_string(),
+        action: action.to_string(),
+        room: room.to_string(),
+    });
+}
+
+async fn control_relay(
+    State(state): State<WebState>,
+    ConnectInfo(source): ConnectInfo<SocketAddr>,
+    CurrentUser(user): CurrentUser,
+    Json(request): Json<RelayControlRequest>,
+) -> Response {
+    if !state.devices.has_room(&request.room) {
+        return api_error(StatusCode::BAD_REQUEST, "Invalid room");
+    }
+    if state.devices.room_relay(&request.room).is_none() {
+        return api_error(StatusCode::BAD_REQUEST, "No relay configured for this room");
+    }
+    if let Err((status, error)) = authorize(&user, &request.room) {
+        return api_error(status, error);
+    }
+    record_web_action(&state, &user, source, if request.state { "relay_on" } else { "relay_off" }, &request.room);
+    run_command(&state, |reply| WebCommand::SetRelay { room: request.room, on: request.state, reply }).await
 }
 
 async fn disable_heater(
     State(state): State<WebState>,
+    ConnectInfo(source): ConnectInfo<SocketAddr>,
+    CurrentUser(user): CurrentUser,
     Json(request): Json<DisableHeaterRequest>,
-) -> axum::Json<serde_json::Value> {
-    let mut server_state = state.server_state.write().await;
-    let room_state_arc = match request.room.as_str() {
-        "bedroom" => &mut server_state.bedroom,
-        "kids_bedroom" => &mut server_state.kids_bedroom,
-        _ => return axum::Json(serde_json::json!({ "success": false, "error": "Invalid room" }))
+) -> Response {
+    if !state.devices.has_room(&request.room) {
+        return api_error(StatusCode::BAD_REQUEST, "Invalid room");
+    }
+    if let Err((status, error)) = authorize(&user, &request.room) {
+        return api_error(status, error);
+    }
+    record_web_action(&state, &user, source, if request.disable { "disable" } else { "enable" }, &request.room);
+    run_command(&state, |reply| WebCommand::Disable { room: request.room, disable: request.disable, reply }).await
+}
+
+// Hands a command to the control task and waits for its result
+async fn run_command(state: &WebState, command: impl FnOnce(oneshot::Sender<Result<(), String>>) -> WebCommand) -> Response {
+    let (reply, result) = oneshot::channel();
+    if state.commands.send(command(reply)).await.is_err() {
+        return api_error(StatusCode::SERVICE_UNAVAILABLE, "Server is shutting down");
+    }
+    match result.await {
+        Ok(Ok(())) => axum::Json(serde_json::json!({ "success": true })).into_response(),
+        Ok(Err(e)) => api_error(StatusCode::BAD_GATEWAY, &e),
+        Err(_) => api_error(StatusCode::SERVICE_UNAVAILABLE, "Server is shutting down"),
+    }
+}
+
+// Seen and configured devices with their message statistics
+async fn get_devices(
+    State(state): State<WebState>,
+    CurrentUser(user): CurrentUser,
+) -> Response {
+    if user.is_none() {
+        return api_error(StatusCode::UNAUTHORIZED, "Login required");
+    }
+    axum::Json(state.inventory.health(&state.devices, Local::now().timestamp())).into_response()
+}
+
+// Current and suggested sensor coefficients, with the readings collected so far
+async fn get_calibration(
+    State(state): State<WebState>,
+    CurrentUser(user): CurrentUser,
+) -> Response {
+    if user.is_none() {
+        return api_error(StatusCode::UNAUTHORIZED, "Login required");
+    }
+    axum::Json(state.calibrator.status()).into_response()
+}
+
+async fn start_calibration(
+    State(state): State<WebState>,
+    ConnectInfo(source): ConnectInfo<SocketAddr>,
+    CurrentUser(user): CurrentUser,
+    Json(request): Json<CalibrationRequest>,
+) -> Response {
+    if let Err((status, error)) = authorize_sensor(&state.devices, &user, &request.sensor) {
+        return api_error(status, error);
+    }
+    if let Err(error) = state.calibrator.start(&request.sensor, Local::now().timestamp()) {
+        return api_error(StatusCode::BAD_REQUEST, error);
+    }
+    record_web_action(&state, &user, source, "calibration_start", &request.sensor);
+    axum::Json(serde_json::json!({ "success": true })).into_response()
+}
+
+// Ends calibration mode, the response carries the suggested coefficients
+async fn stop_calibration(
+    State(state): State<WebState>,
+    ConnectInfo(source): ConnectInfo<SocketAddr>,
+    CurrentUser(user): CurrentUser,
+    Json(request): Json<CalibrationRequest>,
+) -> Response {
+    if let Err((status, error)) = authorize_sensor(&state.devices, &user, &request.sensor) {
+        return api_error(status, error);
+    }
+    let Some(status) = state.calibrator.stop(&request.sensor) else {
+        return api_error(StatusCode::BAD_REQUEST, "Calibration is not active for this sensor");
     };
+    record_web_action(&state, &user, source, "calibration_stop", &request.sensor);
+    axum::Json(status).into_response()
+}
 
-    if request.disable {
-        room_state_arc.disabled_until = Some(Local::now().timestamp() + 2 * 3600);
-        if room_state_arc.relay_state { // if heater is on, turn it off
-            let relay_hostname = match request.room.as_str() {
-                "bedroom" => "esp8266-relay0.local",
-                "kids_bedroom" => "esp8266-relay2.local",
-                _ => unreachable!()
-            };
-            if let Err(e) = set_relay(relay_hostname, false, 0) {
-                return axum::Json(serde_json::json!({ "success": false, "error": e.to_string() }));
-            }
-            room_state_arc.relay_state = false;
-        }
-    } else {
-        room_state_arc.disabled_until = None;
+// Reading of a reference thermometer next to the sensor
+async fn calibration_reference(
+    State(state): State<WebState>,
+    ConnectInfo(source): ConnectInfo<SocketAddr>,
+    CurrentUser(user): CurrentUser,
+    Json(request): Json<ReferenceReadingRequest>,
+) -> Response {
+    if let Err((status, error)) = authorize_sensor(&state.devices, &user, &request.sensor) {
+        return api_error(status, error);
+    }
+    let result = state.calibrator.manual_reference(&request.sensor, Local::now().timestamp(), request.temperature, request.humidity);
+    if let Err(error) = result {
+        return api_error(StatusCode::BAD_REQUEST, error);
     }
-    axum::Json(serde_json::json!({ "success": true }))
+    record_web_action(&state, &user, source, "calibration_reference", &request.sensor);
+    axum::Json(serde_json::json!({ "success": true })).into_response()
 }
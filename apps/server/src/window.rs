@@ -0,0 +1,120 @@
+use serde::Deserialize;
+use std::collections::VecDeque;
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OpenWindowConfig {
+    pub enabled: bool,
+    // A window counts as open when the temperature falls by `temp_drop`
+    // within `lookback_min` minutes
+    pub temp_drop: f64,
+    pub lookback_min: u32,
+    // Optional second trigger: humidity falling by this many %RH within the same time
+    pub humidity_drop: Option<f64>,
+    // Heating stays off this long after a detection
+    pub pause_min: u32,
+}
+
+impl Default for OpenWindowConfig {
+    fn default() -> Self {
+        OpenWindowConfig { enabled: true, temp_drop: 0.6, lookback_min: 5, humidity_drop: None, pause_min: 20 }
+    }
+}
+
+struct Sample {
+    timestamp: i64,
+    temp: f64,
+    humidity: f64,
+}
+
+// Result of a detection, drops are positive
+#[derive(Debug, PartialEq)]
+pub struct WindowOpened {
+    pub temp_drop: f64,
+    pub humidity_drop: f64,
+    pub paused_until: i64,
+}
+
+// Watches the corrected temperature and the humidity of one room
+#[derive(Default)]
+pub struct WindowDetector {
+    samples: VecDeque<Sample>,
+    paused_until: Option<i64>,
+}
+
+impl WindowDetector {
+    // Adds a reading, returns Some when it starts a new pause
+    pub fn update(&mut self, config: &OpenWindowConfig, timestamp: i64, temp: f64, humidity: f64) -> Option<WindowOpened> {
+        let lookback_start = timestamp - config.lookback_min as i64 * 60;
+        while self.samples.front().is_some_and(|sample| sample.timestamp < lookback_start) {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(Sample { timestamp, temp, humidity });
+
+        if !config.enabled || self.is_paused(timestamp) {
+            return None;
+        }
+
+        let max_temp = self.samples.iter().map(|sample| sample.temp).fold(f64::MIN, f64::max);
+        let max_humidity = self.samples.iter().map(|sample| sample.humidity).fold(f64::MIN, f64::max);
+        let temp_drop = max_temp - temp;
+        let humidity_drop = max_humidity - humidity;
+        let humidity_triggered = config.humidity_drop.is_some_and(|limit| humidity_drop >= limit);
+        if temp_drop < config.temp_drop && !humidity_triggered {
+            return None;
+        }
+
+        // Start over, so the same drop does not trigger again after the pause
+        self.samples.clear();
+        self.samples.push_back(Sample { timestamp, temp, humidity });
+        let paused_until = timestamp + config.pause_min as i64 * 60;
+        self.paused_until = Some(paused_until);
+        Some(WindowOpened { temp_drop, humidity_drop, paused_until })
+    }
+
+    // Forgets the readings, e.g. when the temperature source changed
+    pub fn restart(&mut self) {
+        self.samples.clear();
+    }
+
+    pub fn is_paused(&self, timestamp: i64) -> bool {
+        self.paused_until.is_some_and(|until| timestamp < until)
+    }
+
+    pub fn paused_until(&self, timestamp: i64) -> Option<i64> {
+        self.paused_until.filter(|&until| timestamp < until)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_fast_drop() {
+        let config = OpenWindowConfig::default();
+        let mut detector = WindowDetector::default();
+        // Slow cooling, 0.1 per minute
+        for minute in 0..10 {
+            assert_eq!(detector.update(&config, minute * 60, 21.0 - minute as f64 * 0.1, 50.0), None);
+        }
+        assert!(!detector.is_paused(600));
+
+        // 0.7 within two minutes
+        assert_eq!(detector.update(&config, 660, 19.9, 50.0), None);
+        let opened = detector.update(&config, 720, 19.4, 50.0).unwrap();
+        assert_eq!(opened.paused_until, 720 + 20 * 60);
+        assert!(detector.is_paused(780));
+        // Further drops while paused don't restart the pause
+        assert_eq!(detector.update(&config, 780, 18.0, 50.0), None);
+        assert_eq!(detector.paused_until(720 + 20 * 60), None);
+    }
+
+    #[test]
+    fn humidity_trigger() {
+        let config = OpenWindowConfig { humidity_drop: Some(8.0), ..Default::default() };
+        let mut detector = WindowDetector::default();
+        assert_eq!(detector.update(&config, 0, 21.0, 60.0), None);
+        assert!(detector.update(&config, 120, 20.9, 50.0).is_some());
+    }
+}
@@ -14,7 +14,7 @@ struct FragInfo {
 
 const FRAG_MAGIC: u8 = 0xfa;
 const MAX_MESSAGE_SIZE: usize = 65536;
-const MAX_UDP: usize = 1460;
+pub const MAX_UDP: usize = 1460;
 const FRAG_INFO_SZ: usize = 5;
 const MAX_LOG_FRAGMENT: usize = MAX_UDP - FRAG_INFO_SZ;
 
@@ -62,7 +62,11 @@ impl<'a, T: protobuf::Message, H: MessageHandler<T>> FragmentCombiner<'a, T, H>
 
     pub async fn main_loop(&mut self, bind_addr: &str) -> anyhow::Result<()> {
         let socket = UdpSocket::bind(bind_addr).await?;
+        self.serve(socket).await
+    }
 
+    // Same as main_loop with a socket bound by the caller, e.g. passed by systemd
+    pub async fn serve(&mut self, socket: UdpSocket) -> anyhow::Result<()> {
         loop {
             let mut buf = [0; MAX_UDP];
             let (sz, src) = socket.recv_from(&mut buf).await?;
@@ -134,13 +138,28 @@ impl<'a, T: protobuf::Message, H: MessageHandler<T>> FragmentCombiner<'a, T, H>
     }
 }
 
+// Splits a message into datagrams with the framing add_fragment expects.
+// Fails for messages the receiver would drop as too large.
+pub fn fragment(seq: u8, message: &[u8]) -> Result<Vec<Vec<u8>>> {
+    if message.len() > MAX_MESSAGE_SIZE {
+        bail!("message too large: {}", message.len());
+    }
+    let chunks: Vec<&[u8]> = if message.is_empty() { vec![message] } else { message.chunks(MAX_LOG_FRAGMENT).collect() };
+    // At most 46 chunks, the index fits its byte
+    Ok(chunks.iter().enumerate().map(|(i, chunk)| {
+        let mut packet = vec![FRAG_MAGIC, 1, seq, (i + 1 == chunks.len()) as u8, i as u8];
+        packet.extend_from_slice(chunk);
+        packet
+    }).collect())
+}
+
 #[cfg(test)]
 mod tests {
     use std::net::{IpAddr, Ipv4Addr, SocketAddr};
     use tokio;
 
-    use crate::fragment_combiner::{FragmentCombiner, MessageHandler, FRAG_MAGIC};
-    use crate::protos::generated::dev::{DeviceMessage, RelayReport};
+    use crate::fragment_combiner::{fragment, FragmentCombiner, MessageHandler, FRAG_MAGIC, MAX_MESSAGE_SIZE, MAX_UDP};
+    use crate::protos::generated::dev::{DeviceMessage, LogMsg, LoggerProto, RelayReport};
     use protobuf::Message;
 
     struct TestHandler {
@@ -199,4 +218,35 @@ mod tests {
         assert_eq!(err.is_err(), true);
         Ok(())
     }
+    struct Received(Vec<LoggerProto>);
+    impl MessageHandler<LoggerProto> for Received {
+        async fn on_message(&mut self, _src: std::net::SocketAddr, msg: LoggerProto) -> anyhow::Result<()> {
+            self.0.push(msg);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn fragment_round_trip() -> anyhow::Result<()> {
+        for (seq, text_len) in [(1, None), (2, Some(10)), (3, Some(5000))] {
+            let mut msg = LoggerProto::new();
+            if let Some(text_len) = text_len {
+                let mut record = LogMsg::new();
+                record.set_text("x".repeat(text_len));
+                msg.record.push(record);
+            }
+            let packets = fragment(seq, &msg.write_to_bytes()?)?;
+            assert_eq!(packets.len(), if text_len == Some(5000) { 4 } else { 1 });
+            assert!(packets.iter().all(|packet| packet.len() <= MAX_UDP));
+
+            let mut h = Received(Vec::new());
+            let mut f = FragmentCombiner::new(&mut h);
+            for packet in &packets {
+                f.add_fragment(addr(), packet).await?;
+            }
+            assert_eq!(h.0, vec![msg]);
+        }
+        assert!(fragment(4, &vec![0; MAX_MESSAGE_SIZE + 1]).is_err());
+        Ok(())
+    }
 }
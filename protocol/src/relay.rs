@@ -1,15 +1,187 @@
-use crate::protos::generated::dev::{RelayControl, RelayState};
-use anyhow::Result;
+use crate::protos::generated::dev::{DeviceMessage, RelayControl, RelayState};
+use anyhow::{anyhow, Result};
 use protobuf::Message;
-use std::net::UdpSocket;
+use std::collections::HashMap;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-pub fn set_relay(addr: &str, on: bool, delay: u32) -> Result<()> {
-    let udp = UdpSocket::bind("0.0.0.0:0")?;
+const RELAY_PORT: u16 = 4210;
+// mDNS lookups of .local names can take seconds
+const RESOLVE_TIMEOUT: Duration = Duration::from_secs(3);
+// Addresses are looked up again after this long, the old one is used meanwhile
+const RESOLVE_TTL: Duration = Duration::from_secs(300);
+// First retry of a failed lookup
+const RETRY_MIN: Duration = Duration::from_secs(5);
+
+fn relay_control(on: bool, delay: u32) -> Result<Vec<u8>> {
     let mut msg: RelayControl = RelayControl::new();
     msg.set_dummy(true);
     msg.set_state(if on { RelayState::ON } else { RelayState::OFF });
     msg.set_delay(delay);
-    let out_bytes: Vec<u8> = msg.write_to_bytes()?;
-    udp.send_to(&out_bytes, addr.to_owned() + ":4210")?;
+    Ok(msg.write_to_bytes()?)
+}
+
+fn heat_on(on: bool) -> Result<Vec<u8>> {
+    let mut msg = DeviceMessage::new();
+    msg.set_heat_on(on);
+    Ok(msg.write_to_bytes()?)
+}
+
+// Blocking, for command line tools. Async code uses UdpSender.
+pub fn set_relay(addr: &str, on: bool, delay: u32) -> Result<()> {
+    let udp = UdpSocket::bind("0.0.0.0:0")?;
+    udp.send_to(&relay_control(on, delay)?, format!("{}:{}", addr, RELAY_PORT))?;
     Ok(())
 }
+
+// Aggregated heat demand for a device controlling a central heat source, addr is "host:port"
+pub fn send_heat_on(addr: &str, on: bool) -> Result<()> {
+    let udp = UdpSocket::bind("0.0.0.0:0")?;
+    udp.send_to(&heat_on(on)?, addr)?;
+    Ok(())
+}
+
+#[derive(Default)]
+struct Resolved {
+    addr: Option<SocketAddr>,
+    // Due for a lookup, None while one runs
+    next_lookup: Option<Instant>,
+    failures: u32,
+    error: Option<String>,
+}
+
+// Outgoing messages of the server over one shared socket. Host names are resolved in the
+// background and cached, a message to a host not resolved yet fails right away.
+pub struct UdpSender {
+    socket: tokio::net::UdpSocket,
+    resolved: Arc<Mutex<HashMap<String, Resolved>>>,
+}
+
+impl UdpSender {
+    pub async fn bind() -> Result<UdpSender> {
+        Ok(UdpSender {
+            socket: tokio::net::UdpSocket::bind("0.0.0.0:0").await?,
+            resolved: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    pub async fn set_relay(&self, host: &str, on: bool, delay: u32) -> Result<()> {
+        self.send(&relay_control(on, delay)?, &relay_addr(host)).await
+    }
+
+    pub async fn send_heat_on(&self, addr: &str, on: bool) -> Result<()> {
+        self.send(&heat_on(on)?, addr).await
+    }
+
+    // addr is "host:port"
+    pub async fn send(&self, packet: &[u8], addr: &str) -> Result<()> {
+        let addr = self.resolve(addr)?;
+        self.send_to(packet, addr).await
+    }
+
+    pub async fn send_to(&self, packet: &[u8], addr: SocketAddr) -> Result<()> {
+        self.socket.send_to(packet, addr).await?;
+        Ok(())
+    }
+
+    // Starts resolving a relay host before its first command
+    pub fn prefetch_relay(&self, host: &str) {
+        let _ = self.resolve(&relay_addr(host));
+    }
+
+    // Starts resolving "host:port" before its first message
+    pub fn prefetch(&self, addr: &str) {
+        let _ = self.resolve(addr);
+    }
+
+    // Cached address, a due lookup runs in the background
+    fn resolve(&self, addr: &str) -> Result<SocketAddr> {
+        if let Ok(addr) = addr.parse() {
+            return Ok(addr);
+        }
+        let mut resolved = self.resolved.lock().unwrap();
+        let entry = resolved.entry(addr.to_string()).or_default();
+        if entry.next_lookup.map_or(entry.failures == 0 && entry.addr.is_none(), |at| at <= Instant::now()) {
+            entry.next_lookup = None;
+            let resolved = self.resolved.clone();
+            let addr = addr.to_string();
+            tokio::spawn(async move {
+                let result = lookup(&addr).await;
+                let mut resolved = resolved.lock().unwrap();
+                let entry = resolved.entry(addr.clone()).or_default();
+                match result {
+                    Ok(new_addr) => {
+                        entry.addr = Some(new_addr);
+                        entry.failures = 0;
+                        entry.error = None;
+                        entry.next_lookup = Some(Instant::now() + RESOLVE_TTL);
+                    }
+                    // The old address is kept, the lookup is retried with a backoff
+                    Err(e) => {
+                        entry.failures += 1;
+                        entry.error = Some(format!("failed to resolve {}: {}", addr, e));
+                        entry.next_lookup = Some(Instant::now() + retry_after(entry.failures));
+                    }
+                }
+            });
+        }
+        match (entry.addr, &entry.error) {
+            (Some(addr), _) => Ok(addr),
+            (None, Some(error)) => Err(anyhow!("{}", error)),
+            (None, None) => Err(anyhow!("{} is not resolved yet", addr)),
+        }
+    }
+}
+
+fn relay_addr(host: &str) -> String {
+    format!("{}:{}", host, RELAY_PORT)
+}
+
+// Doubles from RETRY_MIN with every failed lookup, up to RESOLVE_TTL
+fn retry_after(failures: u32) -> Duration {
+    RETRY_MIN.saturating_mul(1 << failures.saturating_sub(1).min(16)).min(RESOLVE_TTL)
+}
+
+async fn lookup(addr: &str) -> Result<SocketAddr> {
+    let addrs = tokio::time::timeout(RESOLVE_TIMEOUT, tokio::net::lookup_host(addr)).await
+        .map_err(|_| anyhow!("timed out resolving {}", addr))??;
+    // The socket is IPv4 only
+    addrs.into_iter().find(SocketAddr::is_ipv4).ok_or_else(|| anyhow!("no IPv4 address for {}", addr))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn resolve_in_background() -> Result<()> {
+        let sender = UdpSender::bind().await?;
+        assert!(sender.resolve("127.0.0.1:4210").is_ok());
+        // The first message fails right away, the lookup runs meanwhile
+        let started = Instant::now();
+        assert!(sender.resolve("localhost:4210").is_err());
+        assert!(started.elapsed() < RESOLVE_TIMEOUT);
+        for _ in 0..100 {
+            if sender.resolve("localhost:4210").is_ok() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert!(sender.resolve("localhost:4210")?.ip().is_loopback());
+
+        // A failure is kept until the retry is due
+        assert!(sender.resolve("unknown.invalid:4210").is_err());
+        for _ in 0..100 {
+            if sender.resolved.lock().unwrap()["unknown.invalid:4210"].failures != 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert!(sender.resolve("unknown.invalid:4210").unwrap_err().to_string().contains("unknown.invalid"));
+        assert!(sender.resolved.lock().unwrap()["unknown.invalid:4210"].next_lookup.is_some());
+        assert_eq!(retry_after(1), RETRY_MIN);
+        assert_eq!(retry_after(20), RESOLVE_TTL);
+        Ok(())
+    }
+}
@@ -1,18 +1,24 @@
 import { useState, useEffect, useRef, useCallback } from 'react';
 import RoomCard from './components/RoomCard';
-import { getStatus, controlRelay, disableHeater } from './services/api';
-import { RoomState } from './types';
+import LoginForm from './components/LoginForm';
+import DeviceList from './components/DeviceList';
+import { getStatus, subscribeEvents, controlRelay, disableHeater, getCurrentUser, login, logout } from './services/api';
+import { HeatDemandState, RoomState, StatusEvent, CurrentUserResponse } from './types';
 import './index.css';
 
-const POLLING_INTERVAL = 1000; // 1 seconds for polling
-const ROOM_ID_BEDROOM = 'bedroom';
-const ROOM_ID_KIDS = 'kids_bedroom';
+// Display names of the rooms, others are shown by their API name
+const ROOM_TITLES: Record<string, string> = {
+  bedroom: 'Bedroom',
+  irina: 'Irina',
+  kids_bedroom: 'Kids Bedroom',
+};
 
 function App() {
-  const [bedroomData, setBedroomData] = useState<RoomState | null>(null);
-  const [kidsRoomData, setKidsRoomData] = useState<RoomState | null>(null);
+  const [roomsData, setRoomsData] = useState<Record<string, RoomState>>({});
+  const [heatDemand, setHeatDemand] = useState<HeatDemandState | null>(null);
   const [isLoading, setIsLoading] = useState<boolean>(true);
   const [error, setError] = useState<string | null>(null);
+  const [currentUser, setCurrentUser] = useState<CurrentUserResponse | null>(null);
   const [isDarkMode, setIsDarkMode] = useState(() => {
     // Check if user has a saved preference
     const saved = localStorage.getItem('darkMode');
@@ -23,11 +29,8 @@ function App() {
     return window.matchMedia('(prefers-color-scheme: dark)').matches;
   });
 
-  const lastUpdateTimestampRef = useRef<{ bedroom: number | null; kids_bedroom: number | null }>({
-    bedroom: null,
-    kids_bedroom: null,
-  });
-  const intervalIdRef = useRef<number | null>(null);
+  const lastUpdateTimestampRef = useRef<Record<string, number>>({});
+  const unsubscribeRef = useRef<(() => void) | null>(null);
 
   // Update dark mode class on HTML element
   useEffect(() => {
@@ -50,42 +53,34 @@ function App() {
     setError(null);
 
     try {
-      // Use the latest timestamp from either room for the last_update query parameter
-      const latestTimestampForQuery = Math.max(
-        lastUpdateTimestampRef.current.bedroom || 0,
-        lastUpdateTimestampRef.current.kids_bedroom || 0
-      );
+      // Use the latest timestamp of any room for the last_update query parameter
+      const latestTimestampForQuery = Math.max(0, ...Object.values(lastUpdateTimestampRef.current));
       const queryTimestamp = latestTimestampForQuery > 0 ? latestTimestampForQuery : undefined;
 
       const data = await getStatus(queryTimestamp);
 
       // Merge new history data with existing, avoid full replacement if not needed
-      setBedroomData(prev => ({
-        ...(prev || data.bedroom), // use new data for static fields or if no previous data
-        ...data.bedroom, // override with latest static fields
-        temperature_history: mergeTemperatureHistory(prev?.temperature_history, data.bedroom.temperature_history)
-      }));
+      setRoomsData(prev => Object.fromEntries(Object.entries(data.rooms).map(([room, roomData]) => [room, {
+        ...(prev[room] || roomData), // use new data for static fields or if no previous data
+        ...roomData, // override with latest static fields
+        temperature_history: mergeTemperatureHistory(prev[room]?.temperature_history, roomData.temperature_history)
+      }])));
 
-      setKidsRoomData(prev => ({
-        ...(prev || data.kids_bedroom),
-        ...data.kids_bedroom,
-        temperature_history: mergeTemperatureHistory(prev?.temperature_history, data.kids_bedroom.temperature_history)
-      }));
+      setHeatDemand(data.heat_demand);
 
       // Update last update timestamps from the new data
-      if (data.bedroom.temperature_history.length > 0) {
-        lastUpdateTimestampRef.current.bedroom = data.bedroom.temperature_history[data.bedroom.temperature_history.length - 1].timestamp;
-      }
-      if (data.kids_bedroom.temperature_history.length > 0) {
-        lastUpdateTimestampRef.current.kids_bedroom = data.kids_bedroom.temperature_history[data.kids_bedroom.temperature_history.length - 1].timestamp;
+      for (const [room, roomData] of Object.entries(data.rooms)) {
+        const history = roomData.temperature_history;
+        if (history.length > 0) {
+          lastUpdateTimestampRef.current[room] = history[history.length - 1].timestamp;
+        }
       }
 
     } catch (err) {
       setError(err instanceof Error ? err.message : 'An unknown error occurred.');
       // Keep stale data on error for polling, clear for initial load?
       // if (isInitialLoad) {
-      //   setBedroomData(null);
-      //   setKidsRoomData(null);
+      //   setRoomsData({});
       // }
     } finally {
       if (isInitialLoad) {
@@ -114,39 +109,66 @@ function App() {
   };
 
 
+  const applyEvent = useCallback((event: StatusEvent) => {
+    if (event.type === 'heat_demand') {
+      setHeatDemand(event.state);
+      return;
+    }
+    const room = event.room;
+
+    if (event.type === 'room') {
+      setRoomsData(prev => ({
+        ...prev,
+        [room]: {
+          ...event.state,
+          temperature_history: prev[room]?.temperature_history || [],
+        },
+      }));
+    } else {
+      setRoomsData(prev => prev[room] ? ({
+        ...prev,
+        [room]: {
+          ...prev[room],
+          temperature_history: mergeTemperatureHistory(prev[room].temperature_history, [event.point]),
+        },
+      }) : prev);
+      lastUpdateTimestampRef.current[room] = event.point.timestamp;
+    }
+  }, []);
+
   useEffect(() => {
     fetchStatus(true); // Initial fetch
 
-    const startPolling = () => {
-      if (intervalIdRef.current === null) {
-        intervalIdRef.current = window.setInterval(() => fetchStatus(false), POLLING_INTERVAL);
+    const subscribe = () => {
+      if (unsubscribeRef.current === null) {
+        // Refetch on (re)connect to fill the gap while the stream was down
+        unsubscribeRef.current = subscribeEvents(applyEvent, () => fetchStatus(false));
       }
     };
 
-    const stopPolling = () => {
-      if (intervalIdRef.current !== null) {
-        clearInterval(intervalIdRef.current);
-        intervalIdRef.current = null;
+    const unsubscribe = () => {
+      if (unsubscribeRef.current !== null) {
+        unsubscribeRef.current();
+        unsubscribeRef.current = null;
       }
     };
 
     const handleVisibilityChange = () => {
       if (document.hidden) {
-        stopPolling();
+        unsubscribe();
       } else {
-        fetchStatus(false); // Fetch immediately when tab becomes visible
-        startPolling();
+        subscribe();
       }
     };
 
-    startPolling();
+    subscribe();
     document.addEventListener('visibilitychange', handleVisibilityChange);
 
     return () => {
-      stopPolling();
+      unsubscribe();
       document.removeEventListener('visibilitychange', handleVisibilityChange); // Cleanup on unmount
     };
-  }, [fetchStatus]);
+  }, [fetchStatus, applyEvent]);
 
   const handleApiAction = async (action: () => Promise<any>) => {
     setIsLoading(true); // Indicate loading for the action
@@ -160,6 +182,44 @@ function App() {
     }
   };
 
+  const refreshCurrentUser = useCallback(async () => {
+    try {
+      setCurrentUser(await getCurrentUser());
+    } catch (err) {
+      setError(err instanceof Error ? err.message : 'Failed to fetch current user.');
+    }
+  }, []);
+
+  useEffect(() => {
+    refreshCurrentUser();
+  }, [refreshCurrentUser]);
+
+  const handleLogin = async (name: string, password: string) => {
+    setError(null);
+    try {
+      await login(name, password);
+    } catch (err) {
+      setError(err instanceof Error ? err.message : 'Failed to log in.');
+    }
+    await refreshCurrentUser();
+  };
+
+  const handleLogout = async () => {
+    try {
+      await logout();
+    } catch (err) {
+      setError(err instanceof Error ? err.message : 'Failed to log out.');
+    }
+    await refreshCurrentUser();
+  };
+
+  // Mirrors User::can_control on the server
+  const canControl = (roomApiName: string) => {
+    const user = currentUser?.user;
+    if (!user) return false;
+    return user.role === 'admin' || (user.role === 'operator' && user.rooms.includes(roomApiName));
+  };
+
   const handleControlRelay = (roomApiName: string, state: boolean) => {
     return handleApiAction(() => controlRelay(roomApiName, state));
   };
@@ -168,7 +228,7 @@ function App() {
     return handleApiAction(() => disableHeater(roomApiName, disable));
   };
 
-  if (isLoading && !bedroomData && !kidsRoomData) {
+  if (isLoading && Object.keys(roomsData).length === 0) {
     return (
       <div className="min-h-screen flex items-center justify-center bg-gray-100 dark:bg-gray-900 text-gray-800 dark:text-gray-200">
         Loading initial data...
@@ -182,47 +242,52 @@ function App() {
         <h1 className="text-3xl font-bold text-gray-800 dark:text-gray-200">
           Temperature Control
         </h1>
-        <button
-          onClick={() => setIsDarkMode(!isDarkMode)}
-          className="p-2 rounded-lg bg-gray-200 dark:bg-gray-700 hover:bg-gray-300 dark:hover:bg-gray-600 transition-colors duration-200"
-          aria-label="Toggle dark mode"
-        >
-          {isDarkMode ? (
-            <svg className="w-6 h-6 text-yellow-500" fill="none" stroke="currentColor" viewBox="0 0 24 24">
-              <path strokeLinecap="round" strokeLinejoin="round" strokeWidth={2} d="M12 3v1m0 16v1m9-9h-1M4 12H3m15.364 6.364l-.707-.707M6.343 6.343l-.707-.707m12.728 0l-.707.707M6.343 17.657l-.707.707M16 12a4 4 0 11-8 0 4 4 0 018 0z" />
-            </svg>
-          ) : (
-            <svg className="w-6 h-6 text-gray-700" fill="none" stroke="currentColor" viewBox="0 0 24 24">
-              <path strokeLinecap="round" strokeLinejoin="round" strokeWidth={2} d="M20.354 15.354A9 9 0 018.646 3.646 9.003 9.003 0 0012 21a9.003 9.003 0 008.354-5.646z" />
-            </svg>
-          )}
-        </button>
+        <div className="flex items-center gap-4">
+          <LoginForm currentUser={currentUser} onLogin={handleLogin} onLogout={handleLogout} />
+          <button
+            onClick={() => setIsDarkMode(!isDarkMode)}
+            className="p-2 rounded-lg bg-gray-200 dark:bg-gray-700 hover:bg-gray-300 dark:hover:bg-gray-600 transition-colors duration-200"
+            aria-label="Toggle dark mode"
+          >
+            {isDarkMode ? (
+              <svg className="w-6 h-6 text-yellow-500" fill="none" stroke="currentColor" viewBox="0 0 24 24">
+                <path strokeLinecap="round" strokeLinejoin="round" strokeWidth={2} d="M12 3v1m0 16v1m9-9h-1M4 12H3m15.364 6.364l-.707-.707M6.343 6.343l-.707-.707m12.728 0l-.707.707M6.343 17.657l-.707.707M16 12a4 4 0 11-8 0 4 4 0 018 0z" />
+              </svg>
+            ) : (
+              <svg className="w-6 h-6 text-gray-700" fill="none" stroke="currentColor" viewBox="0 0 24 24">
+                <path strokeLinecap="round" strokeLinejoin="round" strokeWidth={2} d="M20.354 15.354A9 9 0 018.646 3.646 9.003 9.003 0 0012 21a9.003 9.003 0 008.354-5.646z" />
+              </svg>
+            )}
+          </button>
+        </div>
       </header>
       {error && (
         <div className="mb-4 p-3 bg-red-100 dark:bg-red-800 border border-red-400 dark:border-red-600 text-red-700 dark:text-red-200 rounded text-center">
           Error: {error}
         </div>
       )}
+      {heatDemand && (
+        <div className="mb-4 text-center text-lg text-gray-700 dark:text-gray-300">
+          Boiler: {heatDemand.on ? 'ON' : 'OFF'}
+          {heatDemand.rooms.length > 0 ? `, heat requested by ${heatDemand.rooms.join(', ')}` : ', no heat requested'}
+        </div>
+      )}
       <main className="grid grid-cols-1 md:grid-cols-2 gap-6">
-        <RoomCard
-          roomName="Bedroom"
-          roomApiName={ROOM_ID_BEDROOM}
-          roomData={bedroomData}
-          onControlRelay={handleControlRelay}
-          onDisableHeater={handleDisableHeater}
-          isLoading={isLoading && !bedroomData}
-          isDarkMode={isDarkMode}
-        />
-        <RoomCard
-          roomName="Kids Bedroom"
-          roomApiName={ROOM_ID_KIDS}
-          roomData={kidsRoomData}
-          onControlRelay={handleControlRelay}
-          onDisableHeater={handleDisableHeater}
-          isLoading={isLoading && !kidsRoomData}
-          isDarkMode={isDarkMode}
-        />
+        {Object.entries(roomsData).map(([room, roomData]) => (
+          <RoomCard
+            key={room}
+            roomName={ROOM_TITLES[room] || room}
+            roomApiName={room}
+            roomData={roomData}
+            canControl={canControl(room)}
+            onControlRelay={handleControlRelay}
+            onDisableHeater={handleDisableHeater}
+            isLoading={false}
+            isDarkMode={isDarkMode}
+          />
+        ))}
       </main>
+      {currentUser?.user && <DeviceList />}
     </div>
   );
 }
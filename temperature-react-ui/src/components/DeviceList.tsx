@@ -0,0 +1,84 @@
+import React, { useEffect, useState } from 'react';
+import { getDevices } from '../services/api';
+import { DeviceHealth } from '../types';
+
+const REFRESH_MS = 60000;
+
+const formatAge = (timestamp: number | null): string => {
+  if (timestamp === null) return 'never';
+  const minutes = Math.round((Date.now() / 1000 - timestamp) / 60);
+  if (minutes < 1) return 'just now';
+  if (minutes < 120) return `${minutes} min ago`;
+  return `${Math.round(minutes / 60)} h ago`;
+};
+
+const formatErrors = (errors: Record<string, number>): string => {
+  const entries = Object.entries(errors);
+  return entries.length === 0 ? '-' : entries.map(([name, count]) => `${name}: ${count}`).join(', ');
+};
+
+// Inventory of sensors and relays, to spot flaky devices
+const DeviceList: React.FC = () => {
+  const [devices, setDevices] = useState<DeviceHealth[] | null>(null);
+  const [error, setError] = useState<string | null>(null);
+
+  useEffect(() => {
+    const load = () => getDevices()
+      .then((data) => {
+        setDevices(data);
+        setError(null);
+      })
+      .catch((err) => setError(err instanceof Error ? err.message : 'Failed to fetch devices'));
+    load();
+    const timer = setInterval(load, REFRESH_MS);
+    return () => clearInterval(timer);
+  }, []);
+
+  return (
+    <div className="mt-6 bg-white dark:bg-gray-800 shadow-md rounded-lg p-6 overflow-x-auto">
+      <h2 className="text-2xl font-semibold mb-4 text-gray-700 dark:text-gray-300">Devices</h2>
+      {error && <div className="mb-2 text-red-500 dark:text-red-400">{error}</div>}
+      <table className="w-full text-left text-sm text-gray-700 dark:text-gray-300">
+        <thead>
+          <tr className="border-b border-gray-200 dark:border-gray-700">
+            <th className="py-1 pr-3">Device</th>
+            <th className="py-1 pr-3">Room</th>
+            <th className="py-1 pr-3">Last seen</th>
+            <th className="py-1 pr-3">Address</th>
+            <th className="py-1 pr-3">Restarts</th>
+            <th className="py-1 pr-3">Offline</th>
+            <th className="py-1 pr-3">Interval</th>
+            <th className="py-1 pr-3">Errors</th>
+          </tr>
+        </thead>
+        <tbody>
+          {devices?.map((device) => {
+            const current = device.addresses[device.addresses.length - 1];
+            return (
+              <tr key={`${device.kind}-${device.id}`} className="border-b border-gray-100 dark:border-gray-700">
+                <td className={`py-1 pr-3 ${device.available ? 'text-green-600 dark:text-green-400' : 'text-red-500 dark:text-red-400'}`}>
+                  {device.kind} {device.id}{device.configured ? '' : ' (unknown)'}
+                </td>
+                <td className="py-1 pr-3">{device.room ?? '-'}</td>
+                <td className="py-1 pr-3" title={device.first_seen ? `First seen ${new Date(device.first_seen * 1000).toLocaleString()}` : undefined}>
+                  {formatAge(device.last_seen)}
+                </td>
+                <td className="py-1 pr-3" title={device.addresses.map((used) => used.address).join(' → ')}>
+                  {current?.address ?? '-'}{device.addresses.length > 1 ? ` (${device.addresses.length - 1} earlier)` : ''}
+                </td>
+                <td className="py-1 pr-3">{device.restarts}</td>
+                <td className="py-1 pr-3">{Math.round(device.offline_sec / 60)} min</td>
+                <td className="py-1 pr-3">
+                  {device.interval_sec === null ? '-' : `${device.interval_sec.toFixed(0)} s ± ${(device.jitter_sec ?? 0).toFixed(1)}`}
+                </td>
+                <td className="py-1 pr-3">{formatErrors(device.errors)}</td>
+              </tr>
+            );
+          })}
+        </tbody>
+      </table>
+    </div>
+  );
+};
+
+export default DeviceList;
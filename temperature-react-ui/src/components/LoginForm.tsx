@@ -0,0 +1,66 @@
+import React, { useState } from 'react';
+import { CurrentUserResponse } from '../types';
+
+type LoginFormProps = {
+  currentUser: CurrentUserResponse | null;
+  onLogin: (name: string, password: string) => Promise<void>;
+  onLogout: () => Promise<void>;
+};
+
+const LoginForm: React.FC<LoginFormProps> = ({ currentUser, onLogin, onLogout }) => {
+  const [name, setName] = useState('');
+  const [password, setPassword] = useState('');
+
+  if (!currentUser?.auth_enabled) {
+    return null;
+  }
+
+  if (currentUser.user) {
+    return (
+      <div className="flex items-center gap-2 text-gray-700 dark:text-gray-300">
+        <span>{currentUser.user.name} ({currentUser.user.role})</span>
+        <button
+          onClick={onLogout}
+          className="px-3 py-2 rounded-lg bg-gray-200 dark:bg-gray-700 hover:bg-gray-300 dark:hover:bg-gray-600 transition-colors duration-200"
+        >
+          Log out
+        </button>
+      </div>
+    );
+  }
+
+  const handleSubmit = async (e: React.FormEvent) => {
+    e.preventDefault();
+    await onLogin(name, password);
+    setPassword('');
+  };
+
+  return (
+    <form onSubmit={handleSubmit} className="flex items-center gap-2">
+      <input
+        type="text"
+        placeholder="Name"
+        autoComplete="username"
+        value={name}
+        onChange={e => setName(e.target.value)}
+        className="px-2 py-1 rounded border border-gray-300 dark:border-gray-600 bg-white dark:bg-gray-800 text-gray-800 dark:text-gray-200 w-28"
+      />
+      <input
+        type="password"
+        placeholder="Password"
+        autoComplete="current-password"
+        value={password}
+        onChange={e => setPassword(e.target.value)}
+        className="px-2 py-1 rounded border border-gray-300 dark:border-gray-600 bg-white dark:bg-gray-800 text-gray-800 dark:text-gray-200 w-28"
+      />
+      <button
+        type="submit"
+        className="px-3 py-1 rounded-lg bg-blue-600 hover:bg-blue-700 text-white transition-colors duration-200"
+      >
+        Log in
+      </button>
+    </form>
+  );
+};
+
+export default LoginForm;
@@ -1,12 +1,35 @@
 import React from 'react';
 import TemperatureChart from './TemperatureChart';
-import { RoomState } from '../types'; // Assuming types.ts is in src
+import { HeaterFault, RoomMode, RoomState } from '../types'; // Assuming types.ts is in src
 import StatusIcon from './StatusIcon';
 
+const describeFault = (fault: HeaterFault): string => {
+  switch (fault.type) {
+    case 'no_temperature_rise':
+      return `Heater fault: ON for ${Math.round(fault.on_min)} minutes, temperature rose only ${fault.rise.toFixed(1)}°C`;
+    case 'relay_mismatch':
+      return `Relay fault: reports ${fault.reported_on ? 'ON' : 'OFF'}, expected ${fault.commanded_on ? 'ON' : 'OFF'}`;
+  }
+};
+
+const minutesLeft = (until: number): number => Math.max(0, Math.round((until * 1000 - Date.now()) / 60000));
+
+const describeMode = (mode: RoomMode): string => {
+  switch (mode.mode) {
+    case 'force_heat':
+      return `Heating forced by button for ${minutesLeft(mode.until)} minutes`;
+    case 'boost':
+      return `Boost level ${mode.level}: +${mode.degrees.toFixed(1)}°C for ${minutesLeft(mode.until)} minutes`;
+    case 'away':
+      return `Away: at most ${mode.target.toFixed(1)}°C`;
+  }
+};
+
 type RoomCardProps = {
   roomName: string;
   roomApiName: string;
   roomData: RoomState | null;
+  canControl: boolean; // Logged in user may switch this room
   onControlRelay: (room: string, state: boolean) => Promise<void>;
   onDisableHeater: (room: string, disable: boolean) => Promise<void>;
   isLoading: boolean;
@@ -17,12 +40,14 @@ const RoomCard: React.FC<RoomCardProps> = ({
   roomName,
   roomApiName,
   roomData,
+  canControl,
   onControlRelay,
   onDisableHeater,
   isLoading,
   isDarkMode,
 }) => {
   const isHeaterDisabled = Boolean(roomData?.disabled_until && Date.now() < roomData.disabled_until * 1000);
+  const isWindowOpen = Boolean(roomData?.window_open_until && Date.now() < roomData.window_open_until * 1000);
 
   const handleRelayToggle = () => {
     if (!roomData || !roomData.relay_available) return;
@@ -66,10 +91,23 @@ const RoomCard: React.FC<RoomCardProps> = ({
             {roomData?.sensor_available ? 'Available' : 'Unavailable'}
           </span>
         </div>
+        {roomData?.failsafe && (
+          <div className="mt-2 text-lg text-red-500 dark:text-red-400">
+            Sensor silent, heater is running in failsafe mode
+          </div>
+        )}
         {roomData?.sensor_available && (
           <div className="mt-2 text-lg text-gray-600 dark:text-gray-300">
             <p>Current: <span className="font-medium">{roomData.current_temp?.toFixed(1) ?? 'N/A'}</span>°C</p>
             <p>Target: <span className="font-medium">{roomData.target_temp?.toFixed(1) ?? 'N/A'}</span>°C</p>
+            {roomData.sensors?.length > 1 && roomData.sensors.map((sensor) => (
+              <p key={sensor.device_id} className={`text-sm ${sensor.stale ? 'text-red-500 dark:text-red-400' : 'text-gray-500 dark:text-gray-400'}`}>
+                Sensor {sensor.device_id}: {sensor.temperature?.toFixed(1) ?? 'N/A'}°C{sensor.stale ? ' (stale)' : ''}
+              </p>
+            ))}
+            {roomData.rejected_readings > 0 && (
+              <p className="text-sm text-gray-500 dark:text-gray-400">Rejected readings: {roomData.rejected_readings}</p>
+            )}
           </div>
         )}
       </div>
@@ -97,9 +135,9 @@ const RoomCard: React.FC<RoomCardProps> = ({
         <div className="flex flex-col sm:flex-row gap-2 mt-2">
           <button
             onClick={handleRelayToggle}
-            disabled={!roomData?.relay_available || isHeaterDisabled}
+            disabled={!canControl || !roomData?.relay_available || isHeaterDisabled}
             className={`px-6 py-3 text-lg rounded transition-colors duration-200 ${
-              !roomData?.relay_available || isHeaterDisabled
+              !canControl || !roomData?.relay_available || isHeaterDisabled
                 ? 'bg-gray-400 dark:bg-gray-600 text-gray-200 cursor-not-allowed'
                 : roomData?.relay_state
                   ? 'bg-red-600 hover:bg-red-700 text-white'
@@ -117,9 +155,9 @@ const RoomCard: React.FC<RoomCardProps> = ({
 
           <button
             onClick={() => handleHeaterControl(!isHeaterDisabled)}
-            disabled={!roomData?.relay_available}
+            disabled={!canControl || !roomData?.relay_available}
             className={`px-6 py-3 text-lg rounded transition-colors duration-200 ${
-              !roomData?.relay_available
+              !canControl || !roomData?.relay_available
                 ? 'bg-gray-400 dark:bg-gray-600 text-gray-200 cursor-not-allowed'
                 : isHeaterDisabled
                   ? 'bg-green-600 hover:bg-green-700 text-white'
@@ -134,6 +172,30 @@ const RoomCard: React.FC<RoomCardProps> = ({
           </button>
         </div>
 
+        {roomData?.faults?.map((fault) => (
+          <div key={fault.type} className="mt-2 text-lg text-red-500 dark:text-red-400">
+            {describeFault(fault)}
+          </div>
+        ))}
+
+        {roomData?.mode && (
+          <div className="mt-2 text-lg text-orange-500 dark:text-orange-400">
+            {describeMode(roomData.mode)}
+          </div>
+        )}
+
+        {roomData?.pending_switch && (
+          <div className="mt-2 text-lg text-gray-500 dark:text-gray-400">
+            Heater switches {roomData.pending_switch.on ? 'ON' : 'OFF'} in {minutesLeft(roomData.pending_switch.at)} minutes
+          </div>
+        )}
+
+        {isWindowOpen && roomData?.window_open_until && (
+          <div className="mt-2 text-lg text-blue-500 dark:text-blue-400">
+            Open window detected, heating paused for {Math.max(0, Math.round((roomData.window_open_until * 1000 - Date.now()) / 60000))} minutes
+          </div>
+        )}
+
         {isHeaterDisabled && roomData?.disabled_until && (
           <div className="mt-2 text-lg text-yellow-500 dark:text-yellow-400">
             Automatic restore in: {Math.max(0, Math.round((roomData.disabled_until * 1000 - Date.now()) / 60000))} minutes
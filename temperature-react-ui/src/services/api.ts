@@ -1,4 +1,4 @@
-import { ServerStatusResponse, RelayControlRequest, DisableHeaterRequest, ApiResponse } from '../types';
+import { DeviceHealth, ServerStatusResponse, RelayControlRequest, DisableHeaterRequest, ApiResponse, StatusEvent, LoginRequest, CurrentUserResponse } from '../types';
 
 const API_BASE_URL = '/api'; // Assuming the React app is served from the same domain as the API
 
@@ -14,6 +14,16 @@ export async function getStatus(lastUpdate?: number): Promise<ServerStatusRespon
   return response.json();
 }
 
+// Subscribes to live status updates. onResync is called when the stream (re)connects
+// or the server reports dropped events, the caller should refetch the full status then.
+export function subscribeEvents(onEvent: (event: StatusEvent) => void, onResync: () => void): () => void {
+  const source = new EventSource(`${API_BASE_URL}/events`);
+  source.onmessage = (message) => onEvent(JSON.parse(message.data));
+  source.addEventListener('resync', () => onResync());
+  source.onopen = () => onResync();
+  return () => source.close();
+}
+
 export async function controlRelay(roomName: string, state: boolean): Promise<ApiResponse> {
   const payload: RelayControlRequest = { room: roomName, state };
   const response = await fetch(`${API_BASE_URL}/relay`, {
@@ -50,3 +60,41 @@ export async function disableHeater(roomName: string, disable: boolean): Promise
   }
   return response.json();
 }
+
+export async function getDevices(): Promise<DeviceHealth[]> {
+  const response = await fetch(`${API_BASE_URL}/devices`);
+  if (!response.ok) {
+    throw new Error(`Failed to fetch devices: ${response.statusText}`);
+  }
+  return response.json();
+}
+
+export async function getCurrentUser(): Promise<CurrentUserResponse> {
+  const response = await fetch(`${API_BASE_URL}/me`);
+  if (!response.ok) {
+    throw new Error(`Failed to fetch current user: ${response.statusText}`);
+  }
+  return response.json();
+}
+
+export async function login(name: string, password: string): Promise<ApiResponse> {
+  const payload: LoginRequest = { name, password };
+  const response = await fetch(`${API_BASE_URL}/login`, {
+    method: 'POST',
+    headers: { 'Content-Type': 'application/json' },
+    body: JSON.stringify(payload),
+  });
+  const data: ApiResponse = await response.json();
+  if (!response.ok || !data.success) {
+    throw new Error(data.error || `Failed to log in: ${response.statusText}`);
+  }
+  return data;
+}
+
+export async function logout(): Promise<ApiResponse> {
+  const response = await fetch(`${API_BASE_URL}/logout`, { method: 'POST' });
+  if (!response.ok) {
+    throw new Error(`Failed to log out: ${response.statusText}`);
+  }
+  return response.json();
+}
@@ -6,6 +6,29 @@ export interface TemperaturePoint {
   target: number;
   heater_on: boolean;
   is_disabled: boolean;
+  protection?: 'min_on_time' | 'min_off_time' | 'switch_rate'; // Relay protection changed the decision
+  mode?: ButtonAction; // Button mode active for the point
+}
+
+export type ButtonAction = 'force_heat' | 'boost' | 'away';
+
+// Set with the sensor button, times are Unix timestamps in seconds
+export type RoomMode =
+  | { mode: 'force_heat'; until: number }
+  | { mode: 'boost'; level: number; degrees: number; until: number }
+  | { mode: 'away'; target: number };
+
+export type HeaterFault =
+  | { type: 'no_temperature_rise'; on_min: number; rise: number } // Heater ON, room not warming
+  | { type: 'relay_mismatch'; commanded_on: boolean; reported_on: boolean; since: number };
+
+export interface SensorState {
+  device_id: number;
+  temperature: number | null;
+  humidity: number | null;
+  timestamp: number | null; // Unix timestamp in seconds of the last accepted reading
+  stale: boolean; // Left out of the room temperature
+  rejected_readings: number;
 }
 
 export interface RoomState {
@@ -16,24 +39,93 @@ export interface RoomState {
   relay_state: boolean; // true if ON, false if OFF
   temperature_history: TemperaturePoint[];
   disabled_until: number | null; // Unix timestamp in seconds, or null
+  failsafe: boolean; // Sensor is silent, relay follows the configured failsafe
+  window_open_until: number | null; // Heating paused after an open window, Unix timestamp in seconds
+  faults: HeaterFault[];
+  rejected_readings: number; // Implausible sensor readings dropped since server start
+  sensors: SensorState[]; // The room temperature is fused from the fresh ones
+  mode: RoomMode | null; // Forced heat, boost or away set with the sensor button
+  pending_switch: PendingSwitch | null; // Delayed switch the relay was commanded to do
+}
+
+export interface PendingSwitch {
+  on: boolean;
+  at: number; // Unix timestamp in seconds
+}
+
+// Aggregated house heat demand driving the boiler or pump
+export interface HeatDemandState {
+  rooms: string[]; // Rooms and controllers calling for heat
+  weight: number;
+  demand: boolean;
+  on: boolean; // Boiler output, differs from demand during run-on and minimum times
+  since: number | null; // Unix timestamp in seconds of the last switch
 }
 
 export interface ServerStatusResponse {
-  bedroom: RoomState;
-  kids_bedroom: RoomState;
+  rooms: Record<string, RoomState>; // Rooms with devices, by room name
+  heat_demand: HeatDemandState | null; // null when [heat_demand] is not enabled
+}
+
+// Live updates from /api/events (Server-Sent Events)
+export type StatusEvent =
+  | { type: 'room'; room: string; state: RoomState } // temperature_history is always empty
+  | { type: 'point'; room: string; point: TemperaturePoint }
+  | { type: 'heat_demand'; state: HeatDemandState };
+
+export interface AddressUse {
+  address: string;
+  first_seen: number; // Unix timestamps in seconds
+  last_seen: number;
+}
+
+// Device inventory entry from /api/devices
+export interface DeviceHealth {
+  kind: 'sensor' | 'relay';
+  id: number;
+  room: string | null;
+  configured: boolean; // Listed in [devices], or one of the built-in devices
+  available: boolean;
+  first_seen: number | null; // null for configured devices never heard from
+  last_seen: number | null;
+  addresses: AddressUse[]; // Newest last
+  restarts: number;
+  offline_sec: number; // Sum of offline times reported by the device
+  messages: number;
+  interval_sec: number | null; // Mean of recent report intervals
+  jitter_sec: number | null; // Standard deviation of recent report intervals
+  errors: Record<string, number>; // Sensor errors by SensorError name
 }
 
 // For POST request bodies
 export interface RelayControlRequest {
-  room: string; // "bedroom" or "kids_bedroom"
+  room: string; // Room name, e.g. "bedroom"
   state: boolean; // true for ON, false for OFF
 }
 
 export interface DisableHeaterRequest {
-  room: string; // "bedroom" or "kids_bedroom"
+  room: string; // Room name, e.g. "bedroom"
   disable: boolean; // true to disable, false to restore
 }
 
+export interface LoginRequest {
+  name: string;
+  password: string;
+}
+
+export type Role = 'viewer' | 'operator' | 'admin';
+
+export interface User {
+  name: string;
+  role: Role;
+  rooms: string[]; // Rooms an operator may control
+}
+
+export interface CurrentUserResponse {
+  auth_enabled: boolean;
+  user: User | null;
+}
+
 // Generic API response for POSTs
 export interface ApiResponse {
   success: boolean;